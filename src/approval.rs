@@ -0,0 +1,67 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+/// Commands approved to run on this machine, stored as hashes of the manifest
+/// path and command text, similar to `direnv allow`
+#[derive(Debug)]
+pub struct Approvals {
+    path: PathBuf,
+    hashes: BTreeSet<String>,
+}
+
+fn data_dir() -> Result<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+        .map(|d| d.join("resha"))
+        .ok_or(Error::MissingDataDir)
+}
+
+fn approval_hash(manifest: &Path, cmd: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(manifest.to_string_lossy().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(cmd);
+    format!("{:x}", hasher.finalize())
+}
+
+impl Approvals {
+    pub fn load() -> Result<Self> {
+        let path = data_dir()?.join("approved");
+        let hashes = match fs::read_to_string(&path) {
+            Ok(s) => s.lines().map(String::from).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, hashes })
+    }
+
+    pub fn is_approved(&self, manifest: &Path, cmd: &str) -> bool {
+        self.hashes.contains(&approval_hash(manifest, cmd))
+    }
+
+    pub fn approve(&mut self, manifest: &Path, cmd: &str) {
+        self.hashes.insert(approval_hash(manifest, cmd));
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = String::new();
+        for hash in self.hashes.iter() {
+            out.push_str(hash);
+            out.push('\n');
+        }
+        fs::write(&self.path, out)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use std::panic;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinError, JoinSet};
+
+use crate::entry::{Entry, ExecOptions, ReifyResult};
+use crate::error::Result;
+
+/// Entry reified in the background together with its output
+#[derive(Debug)]
+pub struct Reified {
+    pub entry: Entry,
+    pub output: Vec<u8>,
+    pub result: Result<ReifyResult>,
+}
+
+/// Tasks only fail when reifying panicked, keep panicking in the caller
+fn resume(e: JoinError) -> Reified {
+    panic::resume_unwind(e.into_panic())
+}
+
+/// Reify an entry on the blocking thread pool of the tokio runtime
+pub async fn reify(entry: Entry, opts: Arc<ExecOptions>) -> Reified {
+    task::spawn_blocking(move || {
+        let mut output = Vec::new();
+        let result = entry.reify(&mut output, &opts);
+        Reified {
+            entry,
+            output,
+            result,
+        }
+    })
+    .await
+    .unwrap_or_else(resume)
+}
+
+/// Reify entries concurrently with at most `limit` running at the same time,
+/// returned in the order they finish. Entries are started regardless of
+/// `depends_on`, pass dependent entries in separate batches.
+pub async fn reify_all(entries: Vec<Entry>, opts: ExecOptions, limit: usize) -> Vec<Reified> {
+    let opts = Arc::new(opts);
+    let permits = Arc::new(Semaphore::new(limit.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for entry in entries {
+        // Wait for a running entry to finish before starting another one
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let opts = opts.clone();
+        tasks.spawn(async move {
+            let reified = reify(entry, opts).await;
+            drop(permit);
+            reified
+        });
+    }
+
+    let mut finished = Vec::new();
+    while let Some(reified) = tasks.join_next().await {
+        finished.push(reified.unwrap_or_else(resume));
+    }
+    finished
+}
@@ -0,0 +1,62 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::entry::{Entry, ReifyFail, ReifyResult, ReifySuccess};
+use crate::error::Result;
+
+/// Append a JSON line describing an executed entry to the audit log with how
+/// it finished, entries which didn't execute any of their scripts are not
+/// recorded
+pub fn append(
+    log: &Path,
+    manifest: &Path,
+    entry: &Entry,
+    result: &ReifyResult,
+    duration: Duration,
+) -> Result<()> {
+    // Exit code of the script that decided the outcome, none for commands
+    // that were killed
+    let (status, exit_code) = match result {
+        Ok(ReifySuccess::ExecSuccess(_)) => ("ok".to_string(), Some(0)),
+        Ok(ReifySuccess::Noop) => return Ok(()),
+        Err(
+            fail @ (ReifyFail::ExecFail(code)
+            | ReifyFail::BeforeFail(code)
+            | ReifyFail::AfterFail(code)),
+        ) => (fail.to_string(), Some(*code)),
+        Err(fail @ ReifyFail::MissingOutputs) => (fail.to_string(), Some(0)),
+        Err(fail @ (ReifyFail::TimedOut(_) | ReifyFail::Cancelled)) => (fail.to_string(), None),
+        Err(_) => return Ok(()),
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let user = env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_default();
+
+    let record = json!({
+        "timestamp": timestamp,
+        "manifest": manifest.display().to_string(),
+        "entry": entry.name(),
+        "cmd_sha": format!("{:x}", Sha256::digest(entry.script())),
+        "status": status,
+        "exit_code": exit_code,
+        "duration_ms": duration.as_millis() as u64,
+        "user": user,
+    });
+
+    // Write each record with a single call so concurrent runs don't
+    // interleave lines
+    let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+    file.write_all(format!("{record}\n").as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::io;
+use std::path::Path;
+
+use crate::entry::{ExecOptions, ReifySuccess};
+use crate::error::{Error, Result};
+use crate::graph;
+use crate::manifest::Manifest;
+
+/// Reify the entries of a manifest from a cargo build script, writing back
+/// updated shas, and print `cargo:rerun-if-changed` for the manifest and the
+/// inputs of its entries so cargo only runs the build script again when they
+/// change
+pub fn reify_manifest_for_build(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let mut manifest = Manifest::load(path).map_err(|e| e.in_manifest(path))?;
+    let opts = ExecOptions::default();
+    let mut updated = false;
+    for i in graph::topo_order(manifest.entries())? {
+        let e = &mut manifest.entries_mut()[i];
+        for input in e.inputs() {
+            println!("cargo:rerun-if-changed={}", input.display());
+        }
+        // Cargo reads directives from stdout, command output goes to stderr
+        match e.reify(&mut io::stderr(), &opts)? {
+            Ok(ReifySuccess::ExecSuccess(sha)) => {
+                e.set_sha(sha);
+                updated = true;
+            }
+            Ok(ReifySuccess::Noop) => {}
+            Err(source) => {
+                return Err(Error::EntryFailed {
+                    name: e.name().clone(),
+                    source,
+                })
+            }
+        }
+    }
+
+    if updated {
+        manifest.save()?;
+    }
+    Ok(())
+}
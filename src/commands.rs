@@ -0,0 +1,605 @@
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use resha::hash::{Algorithm, Sha256};
+
+use crate::approval::Approvals;
+use crate::completion;
+use crate::diagnostics::{self, Severity};
+use crate::entry::{Entry, ReifySuccess};
+use crate::error::{Error, Result};
+use crate::graph;
+use crate::history;
+use crate::hook;
+use crate::manifest::{self, Manifest};
+use crate::project;
+use crate::report::{Outcome, Reporter, Style};
+use crate::signature;
+use crate::{
+    load_manifest, parse_manifest, relative_path, DiagnosticFormat, Discovery, EntryDiscovery,
+    HookFramework, Order, Shell,
+};
+
+/// Parse all discovered manifests and make sure every named entry exists in
+/// one of them
+fn load_named(discovery: &EntryDiscovery, names: &[String]) -> Result<Vec<Manifest>> {
+    let manifests = discovery
+        .find()?
+        .iter()
+        .map(|path| load_manifest(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    for name in names {
+        let found = manifests.iter().any(|m| m.find_entry(name).is_some());
+        if !found {
+            let names = manifests
+                .iter()
+                .flat_map(|m| m.entries().iter().filter_map(|e| e.name().as_deref()));
+            return Err(Error::unknown_entry(name, names));
+        }
+    }
+
+    Ok(manifests)
+}
+
+/// Delete the existing outputs of an entry and return their paths
+fn remove_outputs(e: &Entry) -> Result<Vec<PathBuf>> {
+    let files = e.output_files();
+    let mut removed = Vec::new();
+    // Declared directories are removed as a whole, globs only the files they
+    // match
+    for dir in e.outputs().into_iter().filter(|o| o.is_dir()) {
+        fs::remove_dir_all(&dir)?;
+        removed.push(dir);
+    }
+    for output in files.into_iter().filter(|o| o.exists()) {
+        fs::remove_file(&output)?;
+        removed.push(output);
+    }
+    Ok(removed)
+}
+
+pub fn allow(discovery: &Discovery) -> Result<bool> {
+    let mut approvals = Approvals::load()?;
+    let project = project::load()?;
+
+    for (hook, script) in project.hooks() {
+        approvals.approve(&project.config_path(), &project.approval_text(script));
+        println!(
+            "{}: approved {hook}",
+            relative_path(&project.config_path()).display()
+        );
+    }
+
+    for path in discovery.find()? {
+        let entries = parse_manifest(&path)?;
+        for e in entries.iter() {
+            approvals.approve(&path, &project.approval_text(&e.script()));
+        }
+        println!(
+            "{}: approved {} entries",
+            relative_path(&path).display(),
+            entries.len()
+        );
+    }
+
+    approvals.save()?;
+    Ok(true)
+}
+
+pub fn prune(discovery: &Discovery, dry_run: bool) -> Result<bool> {
+    let mut clean = true;
+
+    for path in discovery.find()? {
+        let mut manifest = load_manifest(&path)?;
+        let (vanished, kept): (Vec<_>, Vec<_>) = mem::take(manifest.entries_mut())
+            .into_iter()
+            .partition(|e| e.inputs_vanished());
+
+        for e in vanished.iter() {
+            clean = false;
+            println!(
+                "{}: {} '{}'",
+                relative_path(&path).display(),
+                if dry_run { "would prune" } else { "pruned" },
+                e.name().as_deref().unwrap_or("<unnamed>")
+            );
+        }
+
+        if !vanished.is_empty() && !dry_run {
+            *manifest.entries_mut() = kept;
+            manifest.save()?;
+        }
+    }
+
+    // Only fail when entries were left behind
+    Ok(clean || !dry_run)
+}
+
+pub fn clean(discovery: &Discovery) -> Result<bool> {
+    for path in discovery.find()? {
+        let mut manifest = load_manifest(&path)?;
+        let mut updated = false;
+
+        for e in manifest.entries_mut().iter_mut() {
+            if e.outputs().is_empty() {
+                continue;
+            }
+
+            for output in remove_outputs(e)? {
+                println!("removed {}", relative_path(&output).display());
+            }
+            e.clear_sha();
+            updated = true;
+        }
+
+        if updated {
+            manifest.save()?;
+        }
+    }
+
+    Ok(true)
+}
+
+pub fn rebuild(discovery: &EntryDiscovery, names: &[String], all: bool) -> Result<bool> {
+    let reporter = Reporter {
+        style: Style::Tap,
+        color: false,
+        failures_only: false,
+    };
+    let exec_options = project::load()?.exec_options();
+    let mut success = true;
+
+    for mut manifest in load_named(discovery, names)? {
+        let selected = manifest
+            .entries()
+            .iter()
+            .map(|e| all || names.iter().any(|name| e.is_named(name)))
+            .collect::<Vec<_>>();
+
+        let count = selected.iter().filter(|s| **s).count();
+        if count == 0 {
+            continue;
+        }
+        reporter.plan(&mut io::stdout(), count, &relative_path(manifest.path()))?;
+
+        let mut i = 0;
+        for (e, selected) in manifest.entries_mut().iter_mut().zip(selected) {
+            if !selected {
+                continue;
+            }
+            i += 1;
+            let name = e.name().clone().unwrap_or("<unnamed>".into());
+
+            remove_outputs(e)?;
+            e.clear_sha();
+
+            match e.reify(&mut io::stderr(), &exec_options)? {
+                Ok(ReifySuccess::ExecSuccess(sha)) => {
+                    e.set_sha(sha);
+                    reporter.entry(&mut io::stdout(), i, &name, &Outcome::Ok(""))?;
+                }
+                Ok(ReifySuccess::Noop) => {
+                    reporter.entry(&mut io::stdout(), i, &name, &Outcome::Ok("noop"))?;
+                }
+                Err(fail) => {
+                    success = false;
+                    let outcome = Outcome::NotOk(fail.to_string());
+                    reporter.entry(&mut io::stdout(), i, &name, &outcome)?;
+                }
+            }
+        }
+
+        manifest.save()?;
+    }
+
+    Ok(success)
+}
+
+pub fn touch(discovery: &EntryDiscovery, names: &[String]) -> Result<bool> {
+    for mut manifest in load_named(discovery, names)? {
+        let path = relative_path(manifest.path());
+        let mut touched = false;
+
+        for name in names {
+            if let Some(e) = manifest.find_entry_mut(name) {
+                e.set_sha(e.calc_sha()?);
+                println!("{}: touched '{name}'", path.display());
+                touched = true;
+            }
+        }
+        if touched {
+            manifest.save()?;
+        }
+    }
+
+    Ok(true)
+}
+
+pub fn remove(discovery: &EntryDiscovery, names: &[String], delete_outputs: bool) -> Result<bool> {
+    for manifest in load_named(discovery, names)? {
+        let (path, entries) = (manifest.path(), manifest.entries());
+        let selected = entries
+            .iter()
+            .map(|e| names.iter().any(|name| e.is_named(name)))
+            .collect::<Vec<_>>();
+        if !selected.contains(&true) {
+            continue;
+        }
+
+        let text = fs::read_to_string(path)?;
+        let spans = manifest::entry_spans(&text)?;
+        if spans.len() != entries.len() {
+            return Err(Error::ManifestMalformed);
+        }
+
+        let mut output = String::new();
+        let mut kept = 0;
+        for ((e, span), selected) in entries.iter().zip(spans).zip(selected) {
+            if !selected {
+                continue;
+            }
+            output.push_str(&text[kept..span.start]);
+            kept = span.end;
+            if delete_outputs {
+                for removed in remove_outputs(e)? {
+                    println!("removed {}", relative_path(&removed).display());
+                }
+            }
+            println!(
+                "{}: removed '{}'",
+                relative_path(path).display(),
+                e.name().as_deref().unwrap_or_default()
+            );
+        }
+        output.push_str(&text[kept..]);
+        manifest::write(path, &output)?;
+    }
+
+    Ok(true)
+}
+
+pub fn rename(discovery: &EntryDiscovery, old: &str, new: &str) -> Result<bool> {
+    for mut manifest in load_named(discovery, &[old.into()])? {
+        if manifest.find_entry(old).is_none() {
+            continue;
+        }
+        if manifest.find_entry(new).is_some() {
+            return Err(Error::DuplicateEntry(new.into()));
+        }
+
+        for e in manifest.entries_mut().iter_mut() {
+            if e.is_named(old) {
+                e.rename(new);
+            }
+            e.rename_dependency(old, new);
+        }
+        manifest.save()?;
+        println!(
+            "{}: renamed '{old}' to '{new}'",
+            relative_path(manifest.path()).display()
+        );
+    }
+
+    Ok(true)
+}
+
+pub fn fmt(discovery: &Discovery, sort: bool) -> Result<bool> {
+    for path in discovery.find()? {
+        let mut manifest = load_manifest(&path)?;
+        if sort {
+            manifest.sort_entries();
+        }
+
+        let text = fs::read_to_string(&path)?;
+        if text != manifest.dump()? {
+            manifest.save()?;
+            println!("{}: formatted", relative_path(&path).display());
+        }
+    }
+
+    Ok(true)
+}
+
+pub fn list(discovery: &Discovery, order: Order, null: bool) -> Result<bool> {
+    let separator = if null { '\0' } else { '\n' };
+    for path in discovery.find()? {
+        let entries = parse_manifest(&path)?;
+        let order = match order {
+            Order::Manifest => graph::manifest_order(&entries),
+            Order::Topo => graph::topo_order(&entries)?,
+        };
+        for i in order {
+            if let Some(name) = entries[i].name() {
+                print!("{name}{separator}");
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Minimum, mean and maximum of measured durations
+fn stats(samples: &[Duration]) -> (Duration, Duration, Duration) {
+    let min = samples.iter().min().copied().unwrap_or_default();
+    let max = samples.iter().max().copied().unwrap_or_default();
+    let total = samples.iter().sum::<Duration>();
+    let mean = total / samples.len().max(1) as u32;
+    (min, mean, max)
+}
+
+pub fn bench(discovery: &EntryDiscovery, names: &[String], iterations: usize) -> Result<bool> {
+    let exec_options = project::load()?.exec_options();
+    let mut success = true;
+
+    for manifest in load_named(discovery, names)? {
+        let path = manifest.path();
+        for e in manifest
+            .entries()
+            .iter()
+            .filter(|e| names.iter().any(|name| e.is_named(name)))
+        {
+            let name = e.name().as_deref().unwrap_or_default();
+            let mut hashing = Vec::new();
+            let mut running = Vec::new();
+
+            for _ in 0..iterations {
+                let started = Instant::now();
+                e.calc_sha()?;
+                hashing.push(started.elapsed());
+
+                let started = Instant::now();
+                let code = e.exec(&mut io::sink(), &exec_options)?;
+                running.push(started.elapsed());
+                if code != 0 {
+                    success = false;
+                    eprintln!("{name}: command failed with exit code {code}");
+                    break;
+                }
+            }
+
+            println!(
+                "{}: {name} ({} runs)",
+                relative_path(path).display(),
+                running.len()
+            );
+            for (label, samples) in [("hash", &hashing), ("cmd", &running)] {
+                let (min, mean, max) = stats(samples);
+                println!("  {label:<4}  min {min:.1?}  mean {mean:.1?}  max {max:.1?}");
+            }
+        }
+    }
+
+    Ok(success)
+}
+
+pub fn hermetic(discovery: &EntryDiscovery, names: &[String]) -> Result<bool> {
+    let exec_options = project::load()?.exec_options();
+    let mut success = true;
+
+    for manifest in load_named(discovery, names)? {
+        let path = manifest.path();
+        for e in manifest
+            .entries()
+            .iter()
+            .filter(|e| names.iter().any(|name| e.is_named(name)))
+        {
+            let name = e.name().as_deref().unwrap_or_default();
+            println!("{}: {name}", relative_path(path).display());
+            let (code, undeclared) = e.exec_hermetic(&mut io::stderr(), &exec_options)?;
+            if code != 0 {
+                success = false;
+                println!("  command failed with exit code {code}");
+            }
+            for read in &undeclared.reads {
+                success = false;
+                println!("  undeclared input: {}", read.display());
+            }
+            for write in &undeclared.writes {
+                success = false;
+                println!("  undeclared output: {}", write.display());
+            }
+        }
+    }
+
+    Ok(success)
+}
+
+pub fn signed_content(path: &Path) -> Result<bool> {
+    print!("{}", signature::signed_content(path)?);
+    Ok(true)
+}
+
+pub fn shas(discovery: &Discovery) -> Result<bool> {
+    for path in discovery.find()? {
+        for e in parse_manifest(&path)? {
+            let computed = e.calc_sha()?;
+            let recorded = e.sha().as_deref().unwrap_or("-");
+            let status = if recorded == computed {
+                "unchanged"
+            } else {
+                "changed"
+            };
+            println!(
+                "{}\t{}\t{recorded}\t{computed}\t{status}",
+                relative_path(&path).display(),
+                e.name().as_deref().unwrap_or("<unnamed>"),
+            );
+        }
+    }
+
+    Ok(true)
+}
+
+pub fn cache_key(discovery: &EntryDiscovery, names: &[String]) -> Result<bool> {
+    // Leave out the outputs, the key has to be the same before the cache is
+    // restored and after the outputs were generated
+    let mut hasher = Sha256.hasher();
+    for manifest in load_named(discovery, names)? {
+        for e in manifest
+            .entries()
+            .iter()
+            .filter(|e| names.is_empty() || names.iter().any(|name| e.is_named(name)))
+        {
+            let name = e.name().as_deref().unwrap_or_default();
+            hasher.update(format!("{name}\t{}\n", e.calc_inputs_sha()?).as_bytes());
+        }
+    }
+    println!("{}", hasher.finish());
+
+    Ok(true)
+}
+
+pub fn history(names: &[String], runs: usize) -> Result<bool> {
+    for run in history::load(runs)? {
+        let timestamp = run["timestamp"].as_u64().unwrap_or_default();
+        let status = if run["success"].as_bool().unwrap_or_default() {
+            "succeeded"
+        } else {
+            "failed"
+        };
+        println!("run {} ({status})", history::ago(timestamp));
+
+        let entries = run["entries"].as_array().cloned().unwrap_or_default();
+        for e in entries {
+            let name = e["entry"].as_str().unwrap_or("<unnamed>");
+            if !names.is_empty() && !names.iter().any(|n| n == name) {
+                continue;
+            }
+            println!(
+                "  {:<6} {name} ({}, {} ms)",
+                e["status"].as_str().unwrap_or_default(),
+                e["manifest"].as_str().unwrap_or_default(),
+                e["duration_ms"].as_u64().unwrap_or_default()
+            );
+        }
+    }
+
+    Ok(true)
+}
+
+pub fn check_manifest(path: &Path, format: DiagnosticFormat) -> Result<bool> {
+    let diagnostics = diagnostics::check(path)?;
+
+    match format {
+        DiagnosticFormat::Text => {
+            for d in diagnostics.iter() {
+                println!(
+                    "{}:{}:{}: {}: {}",
+                    path.display(),
+                    d.line,
+                    d.column,
+                    d.severity,
+                    d.message
+                );
+            }
+        }
+        DiagnosticFormat::Json => {
+            let json = diagnostics
+                .iter()
+                .map(|d| d.to_json(path))
+                .collect::<Vec<_>>();
+            serde_json::to_writer_pretty(io::stdout(), &json)?;
+            println!();
+        }
+    }
+
+    Ok(!diagnostics.iter().any(|d| d.severity == Severity::Error))
+}
+
+pub fn completions(shell: Shell) -> Result<bool> {
+    print!("{}", completion::script(shell));
+    Ok(true)
+}
+
+pub fn hook(framework: HookFramework, discovery: &Discovery, install: bool) -> Result<bool> {
+    // Run the hook with the same discovery it was generated with
+    let mut entry = vec!["resha --dry-run".to_string()];
+    let search = &discovery.search;
+    if search.recursive {
+        entry.push("-r".into());
+    }
+    if search.follow_symlinks {
+        entry.push("-L".into());
+    }
+    if search.hidden {
+        entry.push("--hidden".into());
+    }
+    if search.r#match != ".resha.yml" {
+        entry.push(format!("--match {}", search.r#match));
+    }
+
+    let mut files = Vec::new();
+    for path in discovery.find()? {
+        if !discovery.manifests.is_empty() {
+            entry.push(relative_path(&path).display().to_string());
+        }
+        for e in parse_manifest(&path)? {
+            files.extend(e.all_files().into_iter().map(|f| relative_path(&f)));
+        }
+        files.push(relative_path(&path));
+    }
+    files.sort();
+    files.dedup();
+    let item = hook::item(framework, &entry.join(" "), &files);
+
+    if !install {
+        print!("repos:\n{item}");
+        return Ok(true);
+    }
+
+    let config = Path::new(".pre-commit-config.yaml");
+    let text = match fs::read_to_string(config) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            fs::write(config, format!("repos:\n{item}")).map_err(Error::file(config))?;
+            return Ok(true);
+        }
+        Err(e) => return Err(Error::file(config)(e)),
+    };
+    if text.contains("id: resha\n") {
+        eprintln!("{} already has a resha hook", config.display());
+        return Ok(true);
+    }
+    // Only append to `repos` when nothing follows it, rewriting the config
+    // would lose its comments
+    let last_key = text
+        .lines()
+        .rev()
+        .find(|line| !line.is_empty() && !line.starts_with([' ', '-', '#']));
+    if last_key.map(str::trim_end) != Some("repos:") {
+        eprintln!("add the hook to repos in {} by hand:", config.display());
+        print!("{item}");
+        return Ok(false);
+    }
+    let separator = if text.ends_with('\n') { "" } else { "\n" };
+    fs::write(config, format!("{text}{separator}{item}")).map_err(Error::file(config))?;
+    Ok(true)
+}
+
+pub fn describe(discovery: &EntryDiscovery, names: &[String]) -> Result<bool> {
+    for manifest in load_named(discovery, names)? {
+        let path = manifest.path();
+        for e in manifest
+            .entries()
+            .iter()
+            .filter(|e| names.iter().any(|name| e.is_named(name)))
+        {
+            println!("manifest: {}", relative_path(path).display());
+            println!("{e:#}");
+            println!("resolved files:");
+            for file in e.all_files() {
+                println!("  {}", file.display());
+            }
+            println!("resolved outputs:");
+            for output in e.output_files() {
+                println!("  {}", output.display());
+            }
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,56 @@
+use crate::Shell;
+
+const SUBCOMMANDS: &str =
+    "allow prune clean rebuild touch remove rename fmt list describe bench hermetic check-manifest signed-content shas cache-key history daemon serve hook completions man help";
+
+/// Subcommands taking entry names, completed by asking `resha list` for the
+/// entries of manifests below the current directory
+const ENTRY_SUBCOMMANDS: &str = "touch rebuild remove rename describe bench hermetic cache-key";
+
+const BASH: &str = r#"_resha() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [[ $COMP_CWORD -eq 1 && "$cur" != -* ]]; then
+        COMPREPLY=($(compgen -W "{subcommands}" -- "$cur"))
+        return
+    fi
+    case " {entry_subcommands} " in
+        *" ${COMP_WORDS[1]} "*)
+            if [[ "$cur" != -* ]]; then
+                COMPREPLY=($(compgen -W "$(resha list -r 2>/dev/null)" -- "$cur"))
+                return
+            fi
+            ;;
+    esac
+    COMPREPLY=($(compgen -f -- "$cur"))
+}
+complete -F _resha resha
+"#;
+
+const ZSH: &str = r#"#compdef resha
+_resha() {
+    if (( CURRENT == 2 )) && [[ ${words[CURRENT]} != -* ]]; then
+        compadd -- {subcommands}
+    elif [[ " {entry_subcommands} " == *" ${words[2]} "* && ${words[CURRENT]} != -* ]]; then
+        compadd -- ${(f)"$(resha list -r 2>/dev/null)"}
+    else
+        _files
+    fi
+}
+compdef _resha resha
+"#;
+
+const FISH: &str = r#"complete -c resha -n __fish_use_subcommand -f -a "{subcommands}"
+complete -c resha -n "__fish_seen_subcommand_from {entry_subcommands}" -f -a "(resha list -r 2>/dev/null)"
+"#;
+
+/// Shell script completing subcommands and entry names
+pub fn script(shell: Shell) -> String {
+    let template = match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    };
+    template
+        .replace("{subcommands}", SUBCOMMANDS)
+        .replace("{entry_subcommands}", ENTRY_SUBCOMMANDS)
+}
@@ -0,0 +1,104 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::{Command, CommandFactory, FromArgMatches};
+use strict_yaml_rust::StrictYamlLoader;
+
+use crate::error::{Error, Result};
+use crate::Args;
+
+/// User config file at `$XDG_CONFIG_HOME/resha/config.yml`, if a config
+/// directory can be found
+fn path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .map(|d| d.join("resha/config.yml"))
+}
+
+/// Load option defaults from the user config file as pairs of argument id
+/// and value
+fn load() -> Result<Vec<(String, String)>> {
+    let Some(path) = path() else {
+        return Ok(Vec::new());
+    };
+    let yaml_str = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let malformed = || Error::ConfigMalformed(path.clone());
+
+    let docs = StrictYamlLoader::load_from_str(&yaml_str)?;
+    let Some(doc) = docs.first() else {
+        return Ok(Vec::new());
+    };
+    doc.as_hash()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(|(k, v)| match (k.as_str(), v.as_str()) {
+            (Some(k), Some(v)) => Ok((k.replace('-', "_"), v.to_string())),
+            _ => Err(malformed()),
+        })
+        .collect()
+}
+
+/// Set the default value of every matching option in a command and its
+/// subcommands
+fn apply(mut cmd: Command, defaults: &[(String, String)]) -> Command {
+    for (id, value) in defaults {
+        if cmd
+            .get_arguments()
+            .any(|a| a.get_id() == id.as_str() && !a.is_positional())
+        {
+            let value = value.clone();
+            cmd = cmd.mut_arg(id, |a| a.default_value(value));
+        }
+    }
+
+    let names = cmd
+        .get_subcommands()
+        .map(|s| s.get_name().to_string())
+        .collect::<Vec<_>>();
+    for name in names {
+        cmd = cmd.mut_subcommand(name, |s| apply(s, defaults));
+    }
+    cmd
+}
+
+/// Ids of the options of a command and its subcommands, the keys `apply`
+/// can set
+fn option_ids(cmd: &Command) -> Vec<String> {
+    let mut ids = cmd
+        .get_arguments()
+        .filter(|a| !a.is_positional())
+        .map(|a| a.get_id().to_string())
+        .collect::<Vec<_>>();
+    for sub in cmd.get_subcommands() {
+        ids.extend(option_ids(sub));
+    }
+    ids
+}
+
+/// Parse command line arguments using the user config file for defaults,
+/// options given on the command line take precedence
+pub fn parse_args() -> Result<Args> {
+    let defaults = load()?;
+    let cmd = Args::command();
+
+    let ids = option_ids(&cmd);
+    for (id, _) in defaults.iter() {
+        if !ids.contains(id) {
+            return Err(Error::unknown_config_key(
+                id,
+                ids.iter().map(String::as_str),
+            ));
+        }
+    }
+
+    let matches = apply(cmd, &defaults).get_matches();
+    Ok(Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()))
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tracing::{info, warn};
+
+use crate::cancel::CancellationToken;
+use crate::error::Result;
+use crate::{
+    isolate_manifest, load_manifest, run_after_all, run_before_all, run_state, Args, FsCache,
+};
+
+/// Serve `status`, `check` and `reify` requests on a unix socket, one request
+/// line per connection, listings and manifests are read again for every
+/// request since files may have changed in between
+pub fn serve(args: &Args, socket: &Path) -> Result<bool> {
+    if let Some(dir) = socket.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // A socket left behind by a daemon that didn't shut down cleanly
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket)?;
+    info!("listening on {}", socket.display());
+
+    let started = Instant::now();
+    let fs_cache = Arc::new(FsCache::default());
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(Into::into)
+            .and_then(|stream| handle(args, &fs_cache, started, stream));
+        if let Err(e) = result {
+            warn!("request failed - {e}");
+        }
+    }
+
+    Ok(true)
+}
+
+fn handle(
+    args: &Args,
+    fs_cache: &Arc<FsCache>,
+    started: Instant,
+    stream: UnixStream,
+) -> Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let mut w = &stream;
+
+    // Files may have changed since the last request
+    fs_cache.forget_listings();
+    fs_cache.forget_fetched();
+    match request.trim() {
+        "status" => {
+            let manifests = args.discovery.find_in(fs_cache)?;
+            writeln!(w, "pid {}", process::id())?;
+            writeln!(w, "uptime {}s", started.elapsed().as_secs())?;
+            writeln!(w, "manifests {}", manifests.len())?;
+        }
+        "check" => {
+            let args = Args {
+                dry_run: true,
+                ..args.clone()
+            };
+            run(&args, fs_cache, &mut w)?;
+        }
+        "reify" => run(args, fs_cache, &mut w)?,
+        other => writeln!(
+            w,
+            "error: unknown request '{other}', expected status, check or reify"
+        )?,
+    }
+    Ok(())
+}
+
+/// Reify all manifests the same way a regular run does, including signature
+/// verification and project hooks, writing the report to a client followed by
+/// the exit code a regular run would have had
+fn run(args: &Args, fs_cache: &Arc<FsCache>, w: &mut dyn Write) -> Result<()> {
+    if let Err(e) = run_manifests(args, fs_cache, w) {
+        writeln!(w, "error: {e}")?;
+        writeln!(w, "exit 1")?;
+    }
+    Ok(())
+}
+
+fn run_manifests(args: &Args, fs_cache: &Arc<FsCache>, w: &mut dyn Write) -> Result<()> {
+    let files = args.discovery.find_in(fs_cache)?;
+    let state = run_state(
+        args,
+        &files,
+        fs_cache.clone(),
+        None,
+        CancellationToken::default(),
+    )?;
+
+    let mut log = Vec::new();
+    let before_all = run_before_all(args, &state, &mut log);
+    w.write_all(&log)?;
+    before_all?;
+
+    for path in files {
+        let mut out = Vec::new();
+        let mut log = Vec::new();
+        let manifest = load_manifest(&path);
+        isolate_manifest(args, &path, manifest, &state, &mut out, &mut log);
+        w.write_all(&log)?;
+        w.write_all(&out)?;
+    }
+
+    let mut out = Vec::new();
+    let mut log = Vec::new();
+    run_after_all(args, &state, &mut out, &mut log);
+    w.write_all(&log)?;
+    w.write_all(&out)?;
+
+    let code = i32::from(state.failed.into_inner());
+    writeln!(w, "exit {code}")?;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use serde_json::{json, Value};
+use strict_yaml_rust::StrictYamlLoader;
+
+use crate::entry::{Entry, FromYaml, ReifyFail};
+use crate::error::{Error, Result};
+use crate::graph;
+use crate::manifest;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Problem found in a manifest at a 1-based line and column
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn to_json(&self, path: &Path) -> Value {
+        json!({
+            "file": path.display().to_string(),
+            "line": self.line,
+            "column": self.column,
+            "severity": self.severity.to_string(),
+            "message": self.message,
+        })
+    }
+}
+
+/// Line number of a byte offset in the text
+fn line(text: &str, offset: usize) -> usize {
+    1 + text[..offset].matches('\n').count()
+}
+
+/// Position of the first line of an entry's text containing `needle`, or
+/// the start of the entry
+fn position(text: &str, span: &Range<usize>, needle: &str) -> (usize, usize) {
+    let start = line(text, span.start);
+    let found = text[span.clone()]
+        .lines()
+        .enumerate()
+        .find_map(|(i, l)| l.find(needle).map(|col| (start + i, col + 1)));
+    found.unwrap_or((start, 1))
+}
+
+/// Validate a manifest reporting schema problems, missing required files and
+/// stale entries
+pub fn check(path: &Path) -> Result<Vec<Diagnostic>> {
+    let text = fs::read_to_string(path).map_err(Error::file(path))?;
+    let canonical = dunce::canonicalize(path).map_err(Error::file(path))?;
+    let dir = canonical
+        .parent()
+        .ok_or_else(|| Error::InvalidPath(path.into()))?;
+    let error = |(line, column): (usize, usize), message: String| Diagnostic {
+        line,
+        column,
+        severity: Severity::Error,
+        message,
+    };
+
+    let docs = match StrictYamlLoader::load_from_str(&text) {
+        Ok(docs) => docs,
+        Err(e) => {
+            let at = (e.marker().line(), e.marker().col() + 1);
+            return Ok(vec![error(at, e.to_string())]);
+        }
+    };
+    let mut items = Vec::new();
+    for doc in docs.iter() {
+        match manifest::document_entries(doc) {
+            Ok(Some((_, entries))) => items.extend(entries),
+            Ok(None) => {}
+            Err(e) => return Ok(vec![error((1, 1), e.to_string())]),
+        }
+    }
+
+    let spans = manifest::entry_spans(&text)
+        .ok()
+        .filter(|spans| spans.len() == items.len());
+    let mut diagnostics = Vec::new();
+    let mut entries = Vec::new();
+    for (i, y) in items.iter().enumerate() {
+        let span = spans
+            .as_ref()
+            .map_or(0..text.len(), |spans| spans[i].clone());
+        let e = match Entry::from_yaml(y) {
+            Ok(e) => e.with_dir(dir),
+            Err(e) => {
+                let at = match &e {
+                    Error::UnknownKey { key, .. } => position(&text, &span, &format!("{key}:")),
+                    _ => (line(&text, span.start), 1),
+                };
+                diagnostics.push(error(at, e.to_string()));
+                continue;
+            }
+        };
+
+        for file in e.missing_required_files() {
+            let at = position(&text, &span, file);
+            diagnostics.push(error(at, format!("Required file doesn't exist - '{file}'")));
+        }
+        if e.lists_no_files() {
+            let (line, column) = position(&text, &span, "sha:");
+            diagnostics.push(Diagnostic {
+                line,
+                column,
+                severity: Severity::Warning,
+                message: "Entry has a sha but lists no files, only changes to cmd rerun it".into(),
+            });
+        }
+        if let Err(ReifyFail::DryFail) = e.dry_run()? {
+            let (line, column) = position(&text, &span, "sha:");
+            diagnostics.push(Diagnostic {
+                line,
+                column,
+                severity: Severity::Warning,
+                message: "Entry is stale, its files or command changed".into(),
+            });
+        }
+        entries.push(e);
+    }
+
+    // Dependencies can only be checked when all entries are valid
+    if entries.len() == items.len() {
+        if let Err(e) = graph::topo_order(&entries) {
+            diagnostics.push(error((1, 1), e.to_string()));
+        }
+    }
+
+    Ok(diagnostics)
+}
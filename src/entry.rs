@@ -1,15 +1,35 @@
+use std::collections::HashSet;
+use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-use duct::cmd;
-use sha2::{Digest, Sha256};
+use clap::ValueEnum;
+use glob::{MatchOptions, Pattern};
+use pathdiff::diff_paths;
 use strict_yaml_rust::StrictYaml as Yaml;
 use thiserror::Error as ThisError;
+use tracing::{debug, trace, warn};
 
+use crate::cancel::CancellationToken;
 use crate::error::{Error, Result};
+use crate::events::{Event, Lines, Observer, Status};
+use crate::executor::{self, Accessed, Command, Executor, Input, Local, Strace};
+use crate::expand;
+use crate::fscache::FsCache;
+use crate::git::{self, GitCache, Shas};
+use crate::hash::{self, Algorithm};
+use crate::limit;
+use crate::output::{Capture, Truncate};
+use crate::profile::{Phase, Profile};
+use crate::remote;
+use crate::suggest;
 
 pub trait FromYaml: Sized {
     fn from_yaml(yaml: &Yaml) -> Result<Self>;
@@ -17,6 +37,102 @@ pub trait FromYaml: Sized {
 
 type Sha = String;
 
+/// Number of temporary directories created so far, keeps their names unique
+static TEMP_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// Tracing target of the digests logged with `Entry::with_debug_hash`
+pub const DEBUG_HASH_TARGET: &str = "resha::debug_hash";
+
+/// Size of the chunks files are read in when hashing
+pub const DEFAULT_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Keys an entry in a manifest may have and what they're for, also listed
+/// in the man page
+pub const KEYS: &[(&str, &str)] = &[
+    ("name", "Name of the entry shown in the output"),
+    (
+        "group",
+        "Group of related entries, reported together and run on their own with --group",
+    ),
+    (
+        "cmd",
+        "Shell script run in the manifest's directory with the project's shell",
+    ),
+    ("before", "Script run before cmd, not included in the hash"),
+    (
+        "after",
+        "Script run after cmd succeeds, not included in the hash",
+    ),
+    (
+        "on_failure",
+        "Script run when cmd fails, with $exit_code and $output_file set",
+    ),
+    (
+        "depends_on",
+        "Names of entries in the same manifest this entry depends on",
+    ),
+    (
+        "required_files",
+        "Input files that must exist for cmd to run",
+    ),
+    (
+        "files",
+        "Other files hashed together with the required files",
+    ),
+    (
+        "outputs",
+        "Files generated by cmd, hashed like files and deleted by resha clean",
+    ),
+    (
+        "exclude",
+        "Glob patterns of files left out when expanding globs and directories",
+    ),
+    (
+        "stdin",
+        "Input piped to cmd, a file path or a mapping with a file or inline text",
+    ),
+    (
+        "stdout_to",
+        "File that the standard output of cmd is written to when it succeeds",
+    ),
+    (
+        "extra_hash",
+        "Arbitrary text mixed into the hash, change it to force a rerun",
+    ),
+    (
+        "hash_mode",
+        "Set to true to include the permissions of files in the hash",
+    ),
+    (
+        "lexical_paths",
+        "Set to true to resolve files without following symlinks",
+    ),
+    (
+        "tracked_only",
+        "Set to true to leave files git doesn't track out of globs and directories",
+    ),
+    (
+        "priority",
+        "high, low or a number, entries with a higher priority start first",
+    ),
+    (
+        "mutex",
+        "Name of a shared resource, entries with the same mutex never run at once",
+    ),
+    (
+        "max_output_lines",
+        "Overrides --max-output-lines for this entry",
+    ),
+    (
+        "sha",
+        "Hash of all files and cmd from the last successful run, updated by resha",
+    ),
+    (
+        "outputs_sha",
+        "Hash of the outputs from the last successful run, updated by resha",
+    ),
+];
+
 #[derive(Debug)]
 pub enum ReifySuccess {
     ExecSuccess(Sha),
@@ -27,21 +143,128 @@ pub enum ReifySuccess {
 pub enum ReifyFail {
     #[error("non-zero exit code")]
     ExecFail(i32),
+    #[error("before hook failed with exit code {0}")]
+    BeforeFail(i32),
+    #[error("after hook failed with exit code {0}")]
+    AfterFail(i32),
     #[error("missing required files")]
     MissingRequiredFiles,
     #[error("dry run, things have changed")]
     DryFail,
+    #[error("command not approved, run 'resha allow'")]
+    NotApproved,
+    #[error("cancelled")]
+    Cancelled,
+    #[error("timed out after {}s", .0.as_secs())]
+    TimedOut(Duration),
+    #[error("generated file modified by hand")]
+    OutputsModified,
+    #[error("declared outputs missing or empty")]
+    MissingOutputs,
+    #[error("sha recorded but no files listed")]
+    NoFilesListed,
+    #[error("inputs have uncommitted changes")]
+    DirtyInputs,
+}
+
+/// How declared outputs are checked after a successful command, whether
+/// every output has to exist or also not be empty
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputCheck {
+    Off,
+    #[default]
+    Exist,
+    NonEmpty,
+}
+
+/// Files a command accessed in a hermetic run without them being declared,
+/// relative to the manifest
+#[derive(Debug, Default)]
+pub struct Undeclared {
+    pub reads: Vec<PathBuf>,
+    pub writes: Vec<PathBuf>,
 }
 
 pub type ReifyResult = core::result::Result<ReifySuccess, ReifyFail>;
 
+/// Options controlling how entry commands are executed
+#[derive(Debug, Default)]
+pub struct ExecOptions {
+    /// Let commands read resha's stdin instead of `/dev/null`
+    pub inherit_stdin: bool,
+    /// Shell to run commands with, `bash` if not set
+    pub shell: Option<String>,
+    /// Only show the first and last lines of longer output
+    pub max_output_lines: Option<usize>,
+    /// Kill commands running longer
+    pub timeout: Option<Duration>,
+    /// Runs commands instead of a local `shell`
+    pub executor: Option<Arc<dyn Executor>>,
+    /// Make outputs read-only after a successful run
+    pub read_only_outputs: bool,
+    /// Fail instead of warn when outputs were modified since the last run
+    pub fail_on_modified_outputs: bool,
+    /// Catch commands that succeed without generating their outputs
+    pub check_outputs: OutputCheck,
+    /// Record the files commands read and write with `strace`, Linux only
+    pub trace: bool,
+    /// Exported to commands as `RESHA_DRY_RUN`
+    pub dry_run: bool,
+}
+
+/// Input piped to the command of an entry
+#[derive(Debug)]
+pub enum Stdin {
+    /// Contents of a file relative to the manifest
+    File(String),
+    /// Inline text from the manifest
+    Text(String),
+}
+
 #[derive(Debug)]
 pub struct Entry {
     name: Option<String>,
+    group: Option<String>,
+    /// Whether `group` comes from the YAML document instead of the entry
+    inherited_group: bool,
+    /// Index of the YAML document of the manifest holding the entry
+    section: usize,
     cmd: String,
+    before: Option<String>,
+    after: Option<String>,
+    on_failure: Option<String>,
+    depends_on: Vec<String>,
     required_files: Vec<String>,
     files: Vec<String>,
+    outputs: Vec<String>,
+    exclude: Vec<String>,
+    stdin: Option<Stdin>,
+    stdout_to: Option<String>,
+    extra_hash: Option<String>,
+    hash_mode: bool,
+    lexical_paths: bool,
+    tracked_only: bool,
+    /// Files tracked by git, listed once when first needed
+    tracked: OnceLock<HashSet<PathBuf>>,
+    priority: Option<String>,
+    mutex: Option<String>,
+    max_output_lines: Option<usize>,
     sha: Option<String>,
+    outputs_sha: Option<String>,
+    /// Digest of the output files from the last `calc_sha`
+    computed_outputs_sha: Mutex<Option<Sha>>,
+    /// Files accessed by the last traced run
+    accessed: Mutex<Accessed>,
+    dir: PathBuf,
+    manifest_path: Option<PathBuf>,
+    extra_input: Vec<u8>,
+    buffer_size: usize,
+    debug_hash: bool,
+    profile: Profile,
+    fs_cache: Arc<FsCache>,
+    git_cache: Option<Arc<GitCache>>,
+    algorithm: Arc<dyn Algorithm>,
+    cancel: CancellationToken,
 }
 
 fn str_vec(y: &Yaml) -> Vec<String> {
@@ -56,59 +279,1025 @@ fn str_vec(y: &Yaml) -> Vec<String> {
     }
 }
 
+/// Expand all files and join them by line breaks for the environment of a
+/// command
+fn env_files(files: &[String]) -> String {
+    files
+        .iter()
+        .map(|f| expand::path(f).unwrap_or_else(|_| f.clone()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Permission bits of a file, only the read-only flag is available outside
+/// of Unix
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// Clear or restore write access, only the owner gets it back on Unix
+#[cfg(unix)]
+fn set_read_only(permissions: &mut fs::Permissions, read_only: bool) {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = permissions.mode();
+    let mode = if read_only {
+        mode & !0o222
+    } else {
+        mode | 0o200
+    };
+    permissions.set_mode(mode);
+}
+
+#[cfg(not(unix))]
+fn set_read_only(permissions: &mut fs::Permissions, read_only: bool) {
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(read_only);
+}
+
+/// Resolve `.` and `..` components without following symlinks
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(res.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                res.pop();
+            }
+            _ => res.push(component),
+        }
+    }
+    res
+}
+
+fn is_glob(file: &str) -> bool {
+    file.contains(['*', '?', '['])
+}
+
+fn bool_value(y: &Yaml) -> Result<bool> {
+    match y.as_str() {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(_) => Err(Error::ManifestMalformed),
+    }
+}
+
+/// `stdin` as a file path, or a mapping with either a `file` or inline
+/// `text`
+fn stdin_value(y: &Yaml) -> Result<Option<Stdin>> {
+    match y {
+        Yaml::BadValue => Ok(None),
+        Yaml::String(file) => Ok(Some(Stdin::File(file.into()))),
+        Yaml::Hash(hash) if hash.len() == 1 => match (y["file"].as_str(), y["text"].as_str()) {
+            (Some(file), None) => Ok(Some(Stdin::File(file.into()))),
+            (None, Some(text)) => Ok(Some(Stdin::Text(text.into()))),
+            _ => Err(Error::ManifestMalformed),
+        },
+        _ => Err(Error::ManifestMalformed),
+    }
+}
+
+/// Scheduling priority, `high` and `low` or any number where higher starts
+/// first
+fn parse_priority(priority: &str) -> Option<i32> {
+    match priority {
+        "high" => Some(1),
+        "normal" => Some(0),
+        "low" => Some(-1),
+        n => n.parse().ok(),
+    }
+}
+
+/// Create a new directory in the temporary directory that only the current
+/// user can access, files in it can't be replaced by symlinks planted by
+/// other users
+fn temp_dir(extension: &str) -> Result<PathBuf> {
+    loop {
+        let count = TEMP_DIRS.fetch_add(1, Ordering::SeqCst);
+        let dir = env::temp_dir().join(format!("resha-{}-{count}.{extension}", process::id()));
+        match private_dir_builder().create(&dir) {
+            Ok(()) => return Ok(dir),
+            // Taken by someone else, try the next name
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::file(&dir)(e)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn private_dir_builder() -> fs::DirBuilder {
+    use std::os::unix::fs::DirBuilderExt;
+    let mut builder = fs::DirBuilder::new();
+    builder.mode(0o700);
+    builder
+}
+
+#[cfg(not(unix))]
+fn private_dir_builder() -> fs::DirBuilder {
+    fs::DirBuilder::new()
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(".resha-tmp");
+    path.into()
+}
+
 impl Entry {
-    pub fn all_files(&self) -> Vec<PathBuf> {
-        let mut all_files = self
+    /// Resolve files and run commands relative to `dir` instead of the
+    /// current working directory
+    pub fn with_dir(mut self, dir: &Path) -> Self {
+        self.dir = dir.to_path_buf();
+        self
+    }
+
+    /// Manifest the entry was loaded from, exported to commands as
+    /// `RESHA_MANIFEST_PATH`
+    pub fn with_manifest_path(mut self, path: &Path) -> Self {
+        self.manifest_path = Some(path.to_path_buf());
+        self
+    }
+
+    /// Place the entry in a YAML document of its manifest, joining the group
+    /// of the document unless it names its own
+    pub fn with_section(mut self, section: usize, group: Option<&str>) -> Self {
+        self.section = section;
+        if self.group.is_none() {
+            self.group = group.map(String::from);
+            self.inherited_group = self.group.is_some();
+        }
+        self
+    }
+
+    /// Mix caller provided bytes into the sha of the entry
+    pub fn with_extra_input(mut self, bytes: &[u8]) -> Self {
+        self.extra_input = bytes.to_vec();
+        self
+    }
+
+    /// Read files in chunks of this size when hashing
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Share file system lookups with other entries of the same run
+    pub fn with_fs_cache(mut self, fs_cache: &Arc<FsCache>) -> Self {
+        self.fs_cache = fs_cache.clone();
+        self
+    }
+
+    /// Reuse shas of entries whose files git reports as unmodified instead of
+    /// hashing them
+    pub fn with_git_cache(mut self, git_cache: &Arc<GitCache>) -> Self {
+        self.git_cache = Some(git_cache.clone());
+        self
+    }
+
+    /// Compute shas with another hash algorithm, changing the algorithm
+    /// changes the sha of every entry
+    pub fn with_algorithm(mut self, algorithm: Arc<dyn Algorithm>) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Stop hashing and kill the command when the token is cancelled
+    pub fn with_cancellation(mut self, cancel: &CancellationToken) -> Self {
+        self.cancel = cancel.clone();
+        self
+    }
+
+    /// Log the digest of every hashed file and the combined sha at debug
+    /// level to `DEBUG_HASH_TARGET`
+    pub fn with_debug_hash(mut self, debug_hash: bool) -> Self {
+        self.debug_hash = debug_hash;
+        self
+    }
+
+    /// Resolve a file listed in the manifest, environment variables are
+    /// checked when parsing
+    fn path(&self, file: &str) -> PathBuf {
+        self.dir
+            .join(expand::path(file).unwrap_or_else(|_| file.into()))
+    }
+
+    /// Whether a file matches one of the `exclude` patterns, relative to
+    /// the manifest directory
+    fn is_excluded(&self, file: &Path) -> bool {
+        let file = file.strip_prefix(&self.dir).unwrap_or(file);
+        self.exclude
+            .iter()
+            .any(|p| Pattern::new(p).is_ok_and(|p| p.matches_path(file)))
+    }
+
+    /// Warn when a listed file only matches a file on disk when ignoring case,
+    /// it would be hashed on case-insensitive file systems but be missing on
+    /// others
+    fn check_case(&self, path: &Path) {
+        let Some(name) = path.file_name() else {
+            return;
+        };
+        let same_name = |n: &OsStr| {
+            n.to_string_lossy()
+                .eq_ignore_ascii_case(&name.to_string_lossy())
+        };
+
+        let on_disk = match self.fs_cache.canonicalize(path) {
+            Ok(canonical) => canonical.file_name().map(OsStr::to_os_string),
+            Err(_) => {
+                let _permit = limit::open_file();
+                path.parent()
+                    .and_then(|dir| fs::read_dir(dir).ok())
+                    .and_then(|rd| rd.flatten().map(|de| de.file_name()).find(|n| same_name(n)))
+            }
+        };
+        if let Some(on_disk) = on_disk.filter(|n| n != name && same_name(n)) {
+            warn!(
+                entry = ?self.name,
+                "{} is named {} on disk, names should match in case",
+                path.display(),
+                on_disk.to_string_lossy()
+            );
+        }
+    }
+
+    /// Files matching a glob, the directories before the first wildcard are
+    /// joined as paths so manifests in non-UTF-8 directories still work
+    fn glob_files(&self, file: &str) -> Result<Vec<PathBuf>> {
+        let expanded = expand::path(file)?;
+        let mut base = self.dir.clone();
+        let mut rest = Vec::new();
+        for component in Path::new(&expanded).components() {
+            let component = component.as_os_str().to_string_lossy();
+            if rest.is_empty() && !is_glob(&component) {
+                base.push(&*component);
+            } else {
+                rest.push(component);
+            }
+        }
+
+        let pattern =
+            Pattern::new(&rest.join("/")).map_err(|_| Error::InvalidPattern(file.into()))?;
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        // Without `**` a pattern can't match deeper than its own components
+        let max_depth = if rest.iter().any(|c| c == "**") {
+            usize::MAX
+        } else {
+            rest.len()
+        };
+
+        Ok(self
+            .fs_cache
+            .walk_files(&base, max_depth)
+            .into_iter()
+            .filter(|path| {
+                path.strip_prefix(&base)
+                    .is_ok_and(|rel| pattern.matches_path_with(rel, options))
+            })
+            .collect())
+    }
+
+    /// Resolve a listed file to the existing files it stands for, globs are
+    /// expanded, directories walked and remote URLs fetched into the cache
+    fn resolve(&self, file: &str) -> Result<Vec<PathBuf>> {
+        if remote::is_url(file) {
+            return Ok(vec![self.fs_cache.fetch(file)?]);
+        }
+
+        let path = self.path(file);
+        // Only expanded globs and directories leave out untracked files
+        let expanded = is_glob(file) || self.fs_cache.is_dir(&path);
+        let tracked = if expanded && self.tracked_only {
+            Some(self.tracked_files()?)
+        } else {
+            None
+        };
+        let files: Vec<PathBuf> = if is_glob(file) {
+            self.glob_files(file)?
+        } else if self.fs_cache.is_dir(&path) {
+            self.fs_cache.walk_files(&path, usize::MAX)
+        } else {
+            self.check_case(&path);
+            vec![path]
+        };
+
+        files
+            .into_iter()
+            .map(|f| {
+                let resolved = if self.lexical_paths {
+                    self.fs_cache.check_exists(&f).map(|_| normalize(&f))
+                } else {
+                    self.fs_cache.canonicalize(&f)
+                };
+                resolved.map_err(Error::from)
+            })
+            .filter(|f| !matches!(f, Ok(f) if self.is_excluded(f)))
+            .filter(|f| match (f, tracked) {
+                (Ok(f), Some(tracked)) => tracked.contains(f),
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Files tracked by git in the repository of the manifest
+    fn tracked_files(&self) -> Result<&HashSet<PathBuf>> {
+        if let Some(tracked) = self.tracked.get() {
+            return Ok(tracked);
+        }
+        let tracked = git::tracked_files(&self.dir)?;
+        Ok(self.tracked.get_or_init(|| tracked))
+    }
+
+    fn stdin_file(&self) -> Option<&String> {
+        match &self.stdin {
+            Some(Stdin::File(file)) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Whether none of the listed input files exist anymore, entries without
+    /// any listed files never vanish
+    pub fn inputs_vanished(&self) -> bool {
+        let mut inputs = self
             .files
             .iter()
             .chain(self.required_files.iter())
-            .flat_map(std::fs::canonicalize)
+            .chain(self.stdin_file())
+            .peekable();
+        inputs.peek().is_some() && inputs.all(|f| !remote::is_url(f) && self.resolves_to_nothing(f))
+    }
+
+    /// Whether a listed file is missing, or a glob or directory matches no
+    /// files, other errors leave it to the run to report
+    fn resolves_to_nothing(&self, file: &str) -> bool {
+        match self.resolve(file) {
+            Ok(files) => files.is_empty(),
+            Err(Error::Io(e)) => e.kind() == io::ErrorKind::NotFound,
+            Err(_) => false,
+        }
+    }
+
+    /// Whether a sha is recorded although no files are listed, so only
+    /// changes to `cmd` make the entry run again
+    pub fn lists_no_files(&self) -> bool {
+        self.sha.is_some()
+            && self.files.is_empty()
+            && self.required_files.is_empty()
+            && self.outputs.is_empty()
+            && self.stdout_to.is_none()
+            && self.stdin_file().is_none()
+    }
+
+    /// Existing local files the listed inputs resolve to
+    pub fn inputs(&self) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .chain(self.required_files.iter())
+            .chain(self.stdin_file())
+            .filter(|f| !remote::is_url(f))
+            .flat_map(|f| self.resolve(f))
+            .flatten()
+            .collect()
+    }
+
+    /// Existing files the declared outputs resolve to
+    pub fn output_files(&self) -> Vec<PathBuf> {
+        self.sorted_files(self.outputs.iter().chain(self.stdout_to.iter()))
+    }
+
+    /// Whether outputs changed since the last run generated them, e.g. by
+    /// being edited by hand, only known after comparing a recorded sha
+    fn outputs_modified(&self) -> bool {
+        let (Some(_), Some(recorded)) = (&self.sha, &self.outputs_sha) else {
+            return false;
+        };
+        // Deleted outputs are simply generated again
+        let exist = self.outputs().iter().all(|o| o.exists());
+        exist && self.computed_outputs_sha.lock().unwrap().as_ref() != Some(recorded)
+    }
+
+    /// Declared generated files, including the `stdout_to` target
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        self.outputs
+            .iter()
+            .chain(self.stdout_to.iter())
+            .map(|f| self.path(f))
+            .collect()
+    }
+
+    /// Existing files the listed inputs resolve to, without the outputs
+    pub fn all_files(&self) -> Vec<PathBuf> {
+        self.sorted_files(
+            self.files
+                .iter()
+                .chain(self.required_files.iter())
+                .chain(self.stdin_file()),
+        )
+    }
+
+    /// Files hashed into the sha, the inputs followed by the outputs that
+    /// aren't also listed as inputs
+    fn hashed_files(&self) -> Vec<PathBuf> {
+        let mut files = self.all_files();
+        let outputs = self
+            .output_files()
+            .into_iter()
+            .filter(|f| !files.contains(f))
+            .collect::<Vec<_>>();
+        files.extend(outputs);
+        files
+    }
+
+    /// Resolve listed files in a stable order without duplicates
+    fn sorted_files<'a>(&self, files: impl Iterator<Item = &'a String>) -> Vec<PathBuf> {
+        let mut all_files = files
+            .flat_map(|f| self.resolve(f))
+            .flatten()
             .collect::<Vec<_>>();
-        all_files.sort();
+        // Sort relative to the manifest so the order doesn't depend on where
+        // the repository is checked out
+        all_files.sort_by_cached_key(|f| diff_paths(f, &self.dir).unwrap_or_else(|| f.clone()));
+        // Hashing a file twice would make the sha depend on how often it's
+        // listed
+        all_files.dedup_by(|a, b| {
+            let duplicate = a == b;
+            if duplicate {
+                warn!(entry = ?self.name, "{} is listed more than once", a.display());
+            }
+            duplicate
+        });
         all_files
     }
 
-    fn calc_sha(&self) -> Result<Sha> {
-        let mut hasher = Sha256::new();
-        let mut buffer = [0; 1024];
-        let all_files = self.all_files();
-        for file in all_files {
-            let input = File::open(&file)?;
-            let mut reader = BufReader::new(input);
+    /// Key of the entry's sha in the git cache, if every file is tracked and
+    /// unmodified
+    fn git_key(&self, all_files: &[PathBuf]) -> Option<String> {
+        // File modes and per file digests need the files
+        if self.hash_mode || self.debug_hash {
+            return None;
+        }
+        let stdin = match &self.stdin {
+            Some(Stdin::Text(text)) => text.as_bytes(),
+            _ => &[],
+        };
+        let settings = [
+            self.cmd.as_bytes(),
+            stdin,
+            self.extra_hash.as_deref().unwrap_or_default().as_bytes(),
+            self.extra_input.as_slice(),
+        ];
+        let git_cache = self.git_cache.as_ref()?;
+        git_cache.key(self.algorithm.as_ref(), all_files, &settings)
+    }
 
-            loop {
-                let count = reader.read(&mut buffer)?;
-                if count == 0 {
-                    break;
+    /// Fail if a listed remote URL can't be fetched, resolving files
+    /// otherwise leaves out what can't be found
+    fn check_urls(&self) -> Result<()> {
+        self.files
+            .iter()
+            .chain(self.required_files.iter())
+            .chain(self.stdin_file())
+            .filter(|f| remote::is_url(f))
+            .try_for_each(|url| self.fs_cache.fetch(url).map(drop))
+    }
+
+    pub fn calc_sha(&self) -> Result<Sha> {
+        self.check_urls()?;
+        let started = Instant::now();
+        let mut hasher = self.algorithm.hasher();
+        let mut buffer = vec![0; self.buffer_size.max(1)];
+        let name = self.name.as_deref().unwrap_or("<unnamed>");
+        let all_files = self.hashed_files();
+
+        // Files git reports as unmodified have the same content as when their
+        // sha was recorded
+        let git_key = self.git_key(&all_files);
+        let cached = git_key
+            .as_ref()
+            .zip(self.git_cache.as_ref())
+            .and_then(|(key, git_cache)| git_cache.get(key));
+        if let Some(shas) = cached {
+            debug!(entry = ?self.name, "unmodified in git, using sha {}", shas.sha);
+            *self.computed_outputs_sha.lock().unwrap() = shas.outputs_sha;
+            self.profile.add(Phase::Hash, started.elapsed());
+            return Ok(shas.sha);
+        }
+
+        let output_files = self.output_files().into_iter().collect::<HashSet<_>>();
+        let mut outputs_hasher = (!output_files.is_empty()).then(|| self.algorithm.hasher());
+        for file in all_files {
+            trace!("hashing {}", file.display());
+            let mut digest = self.debug_hash.then(|| self.algorithm.hasher());
+            let mut output_digest = outputs_hasher
+                .as_mut()
+                .filter(|_| output_files.contains(&file));
+            self.hash_file(&file, &mut buffer, &mut *hasher, |chunk| {
+                if let Some(digest) = digest.as_mut() {
+                    digest.update(chunk);
                 }
-                hasher.update(&buffer[..count]);
+                if let Some(output_digest) = output_digest.as_mut() {
+                    output_digest.update(chunk);
+                }
+            })?;
+            if let Some(digest) = digest {
+                let digest = digest.finish();
+                debug!(target: DEBUG_HASH_TARGET, "{name}: {digest}  {}", file.display());
             }
         }
-        hasher.update(&self.cmd);
-        Ok(format!("{:x}", hasher.finalize()))
+        self.hash_settings(&mut *hasher);
+        let sha = hasher.finish();
+        debug!(entry = ?self.name, "computed sha {sha}");
+        let outputs_sha = outputs_hasher.map(|h| h.finish());
+        *self.computed_outputs_sha.lock().unwrap() = outputs_sha.clone();
+        if let (Some(key), Some(git_cache)) = (git_key, &self.git_cache) {
+            let shas = Shas {
+                sha: sha.clone(),
+                outputs_sha,
+            };
+            git_cache.insert(key, shas);
+        }
+        if self.debug_hash {
+            debug!(target: DEBUG_HASH_TARGET, "{name}: {sha}  (combined)");
+        }
+        self.profile.add(Phase::Hash, started.elapsed());
+        Ok(sha)
     }
 
-    fn exec(&self, w: &mut dyn std::io::Write) -> Result<i32> {
-        let script = vec!["set -xe", &self.cmd].join("\n");
+    /// Sha of the inputs, `cmd` and settings, unlike `calc_sha` it doesn't
+    /// change when the outputs are generated
+    pub fn calc_inputs_sha(&self) -> Result<Sha> {
+        let mut hasher = self.algorithm.hasher();
+        let mut buffer = vec![0; self.buffer_size.max(1)];
+        for file in self.all_files() {
+            self.hash_file(&file, &mut buffer, &mut *hasher, |_| {})?;
+        }
+        self.hash_settings(&mut *hasher);
+        Ok(hasher.finish())
+    }
 
-        let reader = cmd!("bash", "-c", script)
-            .env("files", self.files.join("\n"))
-            .env("required_files", self.required_files.join("\n"))
-            .stderr_to_stdout()
-            .reader()?;
+    /// Add the content of a file to a hasher, passing every chunk read on to
+    /// `also`
+    fn hash_file(
+        &self,
+        file: &Path,
+        buffer: &mut [u8],
+        hasher: &mut dyn hash::Hasher,
+        mut also: impl FnMut(&[u8]),
+    ) -> Result<()> {
+        let _permit = limit::open_file();
+        let mut input = File::open(file).map_err(Error::file(file))?;
+        if self.hash_mode {
+            let meta = input.metadata().map_err(Error::file(file))?;
+            hasher.update(&file_mode(&meta).to_le_bytes());
+        }
 
-        let lines = BufReader::new(reader).lines();
-        for line in lines {
-            match line {
-                Ok(l) => {
-                    writeln!(w, "{}", l)?;
-                }
-                // TODO: Get exit code and return it instead of 1
-                Err(_) => return Ok(1),
+        // Read straight into the buffer, a BufReader would only add a copy
+        loop {
+            if self.cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let count = input.read(buffer).map_err(Error::file(file))?;
+            if count == 0 {
+                return Ok(());
+            }
+            hasher.update(&buffer[..count]);
+            also(&buffer[..count]);
+        }
+    }
+
+    /// Add `cmd` and the other settings that aren't files to a hasher
+    fn hash_settings(&self, hasher: &mut dyn hash::Hasher) {
+        hasher.update(self.cmd.as_bytes());
+        if let Some(Stdin::Text(text)) = &self.stdin {
+            hasher.update(text.as_bytes());
+        }
+        if let Some(extra_hash) = &self.extra_hash {
+            hasher.update(extra_hash.as_bytes());
+        }
+        hasher.update(&self.extra_input);
+    }
+
+    /// Run the command regardless of the recorded sha and return its exit code
+    pub fn exec(&self, w: &mut dyn std::io::Write, opts: &ExecOptions) -> Result<i32> {
+        let started = Instant::now();
+
+        // Write stdout to a temporary file which replaces the target only
+        // when the command succeeds
+        let stdout_to = self.stdout_to.as_ref().map(|f| self.path(f));
+        let tmp = stdout_to.as_deref().map(tmp_path);
+        if let Some(dir) = tmp.as_deref().and_then(Path::parent) {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Commands waiting for input would otherwise hang on the terminal
+        let stdin = match &self.stdin {
+            Some(Stdin::File(file)) => Input::File(self.path(file)),
+            Some(Stdin::Text(text)) => Input::Bytes(text.as_bytes().to_vec()),
+            None if opts.inherit_stdin => Input::Inherit,
+            None => Input::Null,
+        };
+        let mut command = self.command(&self.cmd, Vec::new(), opts);
+        command.stdin = stdin;
+        command.stdout_to = tmp.clone();
+        let code = if opts.trace {
+            self.run_traced(&command, w, opts)?
+        } else {
+            self.run(&command, w, opts)?
+        };
+
+        if let (Some(stdout_to), Some(tmp)) = (stdout_to, tmp) {
+            if code == 0 {
+                fs::rename(tmp, &stdout_to).map_err(Error::file(&stdout_to))?;
+            } else {
+                let _ = fs::remove_file(tmp);
+            }
+        }
+
+        // The command may have created files in walked directories
+        self.fs_cache.forget_listings();
+        self.profile.add(Phase::Exec, started.elapsed());
+        Ok(code)
+    }
+
+    /// Command running a script of the entry in the manifest's directory with
+    /// the entry's files and context in its environment
+    fn command(
+        &self,
+        script: &str,
+        env: Vec<(&'static str, String)>,
+        opts: &ExecOptions,
+    ) -> Command<'_> {
+        let manifest_path = self.manifest_path.as_deref().unwrap_or(Path::new(""));
+        Command {
+            script: ["set -xe", script].join("\n"),
+            dir: &self.dir,
+            env: [
+                ("files", env_files(&self.files)),
+                ("required_files", env_files(&self.required_files)),
+                ("outputs", env_files(&self.outputs)),
+                ("RESHA_ENTRY_NAME", self.name.clone().unwrap_or_default()),
+                ("RESHA_MANIFEST_PATH", manifest_path.display().to_string()),
+                ("RESHA_MANIFEST_DIR", self.dir.display().to_string()),
+                ("RESHA_DRY_RUN", u8::from(opts.dry_run).to_string()),
+            ]
+            .into_iter()
+            .chain(env)
+            .collect(),
+            stdin: Input::Null,
+            stdout_to: None,
+            cancel: self.cancel.clone(),
+            timeout: opts.timeout,
+        }
+    }
+
+    /// Run a command with the configured executor, a local shell if there is
+    /// none
+    fn run(
+        &self,
+        command: &Command,
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+    ) -> Result<i32> {
+        let local = Local {
+            shell: opts.shell.clone(),
+        };
+        let executor = opts.executor.as_deref().unwrap_or(&local);
+        self.run_with(executor, command, w, opts)
+    }
+
+    fn run_with(
+        &self,
+        executor: &dyn Executor,
+        command: &Command,
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+    ) -> Result<i32> {
+        let mut w = Truncate::new(w, self.max_output_lines.or(opts.max_output_lines));
+        let code = executor.execute(command, &mut w)?;
+        w.finish()?;
+        Ok(code)
+    }
+
+    /// Run a command under `strace` and remember the files it accessed
+    fn run_traced(
+        &self,
+        command: &Command,
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+    ) -> Result<i32> {
+        let tmp = temp_dir("strace")?;
+        let strace = Strace {
+            shell: opts.shell.clone(),
+            log: tmp.join("strace.log"),
+        };
+        let code = self.run_with(&strace, command, w, opts);
+        let trace = fs::read_to_string(&strace.log).unwrap_or_default();
+        let _ = fs::remove_dir_all(&tmp);
+        *self.accessed.lock().unwrap() = executor::accessed_files(&trace, &self.dir);
+        code
+    }
+
+    /// Files written by the last traced run that aren't listed in the entry,
+    /// relative to the manifest
+    pub fn undeclared_outputs(&self) -> Vec<PathBuf> {
+        self.unlisted(&self.accessed.lock().unwrap().written)
+    }
+
+    /// Files read by the last traced run that aren't listed in the entry,
+    /// changes to them wouldn't change the sha
+    pub fn undeclared_inputs(&self) -> Vec<PathBuf> {
+        let accessed = self.accessed.lock().unwrap();
+        // Files the command generated itself aren't inputs
+        let read = accessed
+            .read
+            .iter()
+            .filter(|f| !accessed.written.contains(f))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.unlisted(&read)
+    }
+
+    /// Existing files below the manifest's directory that aren't listed in
+    /// the entry, relative to the manifest
+    fn unlisted(&self, files: &[PathBuf]) -> Vec<PathBuf> {
+        let Ok(dir) = fs::canonicalize(&self.dir) else {
+            return Vec::new();
+        };
+        let listed = self.hashed_files().into_iter().collect::<HashSet<_>>();
+        let mut files = files
+            .iter()
+            .filter_map(|f| fs::canonicalize(f).ok())
+            .filter(|f| f.starts_with(&dir) && f.is_file() && !listed.contains(f))
+            .filter_map(|f| diff_paths(f, &dir))
+            .collect::<Vec<_>>();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Run the command under `strace` in a temporary directory holding copies
+    /// of only the declared inputs, and return its exit code along with the
+    /// files it accessed outside the declared inputs and outputs
+    pub fn exec_hermetic(
+        &self,
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+    ) -> Result<(i32, Undeclared)> {
+        let sandbox = temp_dir("hermetic")?;
+        let res = self.exec_in_sandbox(&sandbox, w, opts);
+        let _ = fs::remove_dir_all(&sandbox);
+        res
+    }
+
+    fn exec_in_sandbox(
+        &self,
+        sandbox: &Path,
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+    ) -> Result<(i32, Undeclared)> {
+        let dir = fs::canonicalize(&self.dir).map_err(Error::file(&self.dir))?;
+        let inputs = self
+            .inputs()
+            .iter()
+            .filter_map(|f| fs::canonicalize(f).ok())
+            .collect::<HashSet<_>>();
+        let outputs = self
+            .outputs
+            .iter()
+            .chain(self.stdout_to.iter())
+            .map(|f| normalize(&dir.join(f)))
+            .collect::<Vec<_>>();
+
+        // Mirror the closest directory containing the manifest, inputs and
+        // outputs so relative paths between them keep working
+        let root = dir
+            .ancestors()
+            .find(|a| inputs.iter().chain(&outputs).all(|f| f.starts_with(a)))
+            .unwrap_or(dir.as_path())
+            .to_path_buf();
+        let mirror = |path: &Path| sandbox.join(path.strip_prefix(&root).unwrap_or(path));
+        let real = |path: &PathBuf| {
+            let path = normalize(path);
+            if let Ok(rel) = path.strip_prefix(sandbox) {
+                return Some(root.join(rel));
+            }
+            // Escaped the sandbox into the actual tree
+            path.starts_with(&root).then_some(path)
+        };
+
+        for input in &inputs {
+            let copy = mirror(input);
+            if let Some(parent) = copy.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(input, &copy).map_err(Error::file(input))?;
+        }
+        for output in &outputs {
+            if let Some(parent) = mirror(output).parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let sandbox_dir = mirror(&dir);
+        fs::create_dir_all(&sandbox_dir)?;
+
+        let mut command = self.command(&self.cmd, Vec::new(), opts);
+        command.dir = &sandbox_dir;
+        command.stdin = match &self.stdin {
+            Some(Stdin::File(file)) => Input::File(mirror(&normalize(&dir.join(file)))),
+            Some(Stdin::Text(text)) => Input::Bytes(text.as_bytes().to_vec()),
+            None => Input::Null,
+        };
+        command.stdout_to = self
+            .stdout_to
+            .as_ref()
+            .map(|f| mirror(&normalize(&dir.join(f))));
+        let tmp = temp_dir("strace")?;
+        let strace = Strace {
+            shell: opts.shell.clone(),
+            log: tmp.join("strace.log"),
+        };
+        let code = self.run_with(&strace, &command, w, opts);
+        let trace = fs::read_to_string(&strace.log).unwrap_or_default();
+        let _ = fs::remove_dir_all(&tmp);
+        let code = code?;
+
+        let accessed = executor::accessed_files(&trace, &sandbox_dir);
+        let declared_output = |f: &PathBuf| outputs.iter().any(|o| f.starts_with(o));
+        let written = accessed
+            .written
+            .iter()
+            .filter_map(real)
+            .collect::<HashSet<_>>();
+        let relative = |files: Vec<PathBuf>| {
+            let mut files = files
+                .into_iter()
+                .filter_map(|f| diff_paths(f, &dir))
+                .collect::<Vec<_>>();
+            files.sort();
+            files.dedup();
+            files
+        };
+        let writes = written
+            .iter()
+            .filter(|f| !declared_output(f))
+            .cloned()
+            .collect();
+        // Only files missing from the sandbox that exist in the actual tree
+        // are dependencies, commands probe for plenty of files
+        let reads = accessed
+            .read
+            .iter()
+            .chain(&accessed.missing)
+            .filter_map(real)
+            .filter(|f| f.is_file() && !inputs.contains(f) && !written.contains(f))
+            .filter(|f| !declared_output(f))
+            .collect();
+        let undeclared = Undeclared {
+            reads: relative(reads),
+            writes: relative(writes),
+        };
+        Ok((code, undeclared))
+    }
+
+    /// Run the `before` and `after` hooks around `cmd`, hooks aren't part of
+    /// the sha
+    fn exec_with_hooks(
+        &self,
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+    ) -> Result<core::result::Result<(), ReifyFail>> {
+        // Let the command overwrite outputs of previous runs
+        if opts.read_only_outputs {
+            self.set_outputs_read_only(false)?;
+        }
+
+        if let Some(before) = &self.before {
+            let code = self.run(&self.command(before, Vec::new(), opts), w, opts)?;
+            if code != 0 {
+                return Ok(Err(ReifyFail::BeforeFail(code)));
+            }
+        }
+
+        let (code, output) = match &self.on_failure {
+            Some(_) => {
+                let mut capture = Capture::new(w);
+                let code = self.exec(&mut capture, opts)?;
+                (code, capture.captured)
+            }
+            None => (self.exec(w, opts)?, Vec::new()),
+        };
+        if code != 0 {
+            if let Some(on_failure) = &self.on_failure {
+                self.run_on_failure(on_failure, code, &output, w, opts)?;
             }
+            return Ok(Err(ReifyFail::ExecFail(code)));
         }
 
-        Ok(0)
+        if let Some(after) = &self.after {
+            let code = self.run(&self.command(after, Vec::new(), opts), w, opts)?;
+            // The command may have changed walked directories
+            self.fs_cache.forget_listings();
+            if code != 0 {
+                return Ok(Err(ReifyFail::AfterFail(code)));
+            }
+        }
+
+        let missing = self.missing_outputs(opts.check_outputs);
+        if !missing.is_empty() {
+            warn!(entry = ?self.name, "missing or empty outputs: {missing:?}");
+            return Ok(Err(ReifyFail::MissingOutputs));
+        }
+
+        if opts.read_only_outputs {
+            self.set_outputs_read_only(true)?;
+        }
+        Ok(Ok(()))
+    }
+
+    /// Declared outputs that weren't generated, globs and directories need to
+    /// match at least one file
+    fn missing_outputs(&self, check: OutputCheck) -> Vec<&String> {
+        if check == OutputCheck::Off {
+            return Vec::new();
+        }
+        self.outputs
+            .iter()
+            .chain(self.stdout_to.iter())
+            .filter(|f| {
+                let files = self.resolve(f).unwrap_or_default();
+                let empty = || {
+                    files
+                        .iter()
+                        .any(|f| !fs::metadata(f).is_ok_and(|m| m.len() > 0))
+                };
+                files.is_empty() || (check == OutputCheck::NonEmpty && empty())
+            })
+            .collect()
+    }
+
+    /// Change write access to the existing output files, discouraging edits
+    /// by hand
+    fn set_outputs_read_only(&self, read_only: bool) -> Result<()> {
+        for output in self.outputs.iter().chain(self.stdout_to.iter()) {
+            for path in self.resolve(output).unwrap_or_default() {
+                let mut permissions = fs::metadata(&path)
+                    .map_err(Error::file(&path))?
+                    .permissions();
+                set_read_only(&mut permissions, read_only);
+                fs::set_permissions(&path, permissions).map_err(Error::file(&path))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the `on_failure` script with the exit code of `cmd` and the path
+    /// of a file holding its output, the entry fails either way
+    fn run_on_failure(
+        &self,
+        script: &str,
+        code: i32,
+        output: &[u8],
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+    ) -> Result<()> {
+        let tmp = temp_dir("on-failure")?;
+        let path = tmp.join("output.log");
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(output));
+        if let Err(e) = written {
+            let _ = fs::remove_dir_all(&tmp);
+            return Err(Error::file(&path)(e));
+        }
+
+        let env = vec![
+            ("exit_code", code.to_string()),
+            ("output_file", path.display().to_string()),
+        ];
+        let result = self.run(&self.command(script, env, opts), w, opts);
+        let _ = fs::remove_dir_all(&tmp);
+        let code = result?;
+        if code != 0 {
+            warn!(entry = ?self.name, "on_failure script failed with exit code {code}");
+        }
+        Ok(())
     }
 
     fn check_then<F>(&self, exec: F) -> Result<ReifyResult>
@@ -131,29 +1320,72 @@ impl Entry {
         }
     }
 
-    pub fn reify(&self, w: &mut dyn std::io::Write) -> Result<ReifyResult> {
+    pub fn reify(&self, w: &mut dyn std::io::Write, opts: &ExecOptions) -> Result<ReifyResult> {
         let exec = || {
-            self.exec(w).and_then(|code| {
-                if code == 0 {
-                    self.calc_sha()
-                        .and_then(|sha| Ok(Ok(ReifySuccess::ExecSuccess(sha))))
-                } else {
-                    Ok(Err(ReifyFail::ExecFail(code)))
+            if self.outputs_modified() {
+                warn!(entry = ?self.name, "generated file modified by hand");
+                if opts.fail_on_modified_outputs {
+                    return Ok(Err(ReifyFail::OutputsModified));
                 }
-            })
+            }
+            match self.exec_with_hooks(w, opts)? {
+                Ok(()) => self
+                    .calc_sha()
+                    .map(|sha| Ok(ReifySuccess::ExecSuccess(sha))),
+                Err(fail) => Ok(Err(fail)),
+            }
         };
 
-        match self
-            .required_files
-            .iter()
-            .map(std::fs::canonicalize)
-            .collect::<core::result::Result<Vec<_>, _>>()
-        {
-            Err(_) => Ok(Err(ReifyFail::MissingRequiredFiles)),
-            Ok(_) => self.check_then(exec),
+        let result = if self.missing_required_files().is_empty() {
+            self.check_then(exec)
+        } else {
+            Ok(Err(ReifyFail::MissingRequiredFiles))
+        };
+        match result {
+            Err(Error::Cancelled) => Ok(Err(ReifyFail::Cancelled)),
+            Err(Error::TimedOut(timeout)) => Ok(Err(ReifyFail::TimedOut(timeout))),
+            result => result,
         }
     }
 
+    /// Reify the entry reporting when it starts, every line of output and how
+    /// it finished to an observer, output is still written to `w`
+    pub fn reify_observed(
+        &self,
+        w: &mut dyn std::io::Write,
+        opts: &ExecOptions,
+        observer: &dyn Observer,
+    ) -> Result<ReifyResult> {
+        observer.event(Event::Started {
+            entry: self.name.clone(),
+        });
+        let mut lines = Lines::new(w, observer, self.name.clone());
+        let result = self.reify(&mut lines, opts);
+        lines.finish()?;
+
+        let status = match &result {
+            Ok(Ok(ReifySuccess::ExecSuccess(_))) => Status::Executed,
+            Ok(Ok(ReifySuccess::Noop)) => Status::Unchanged,
+            Ok(Err(fail)) => Status::Failed(fail.to_string()),
+            Err(e) => Status::Error(e.to_string()),
+        };
+        observer.event(Event::Finished {
+            entry: self.name.clone(),
+            status,
+        });
+        result
+    }
+
+    /// Required files and stdin file that don't exist, globs and directories
+    /// need to match at least one file
+    pub fn missing_required_files(&self) -> Vec<&String> {
+        self.required_files
+            .iter()
+            .chain(self.stdin_file())
+            .filter(|f| !self.resolve(f).is_ok_and(|files| !files.is_empty()))
+            .collect()
+    }
+
     pub fn dry_run(&self) -> Result<ReifyResult> {
         self.check_then(|| Ok(Err(ReifyFail::DryFail)))
     }
@@ -165,11 +1397,36 @@ impl Entry {
             writeln!(w, "  name: {}", name)?;
         }
 
+        if let Some(group) = self.group.as_ref().filter(|_| !self.inherited_group) {
+            writeln!(w, "  group: {group}")?;
+        }
+
         writeln!(w, "  cmd: |")?;
         for line in self.cmd.lines() {
             writeln!(w, "    {}", line)?;
         }
 
+        let hooks = [
+            ("before", &self.before),
+            ("after", &self.after),
+            ("on_failure", &self.on_failure),
+        ];
+        for (key, hook) in hooks {
+            if let Some(hook) = hook {
+                writeln!(w, "  {key}: |")?;
+                for line in hook.lines() {
+                    writeln!(w, "    {}", line)?;
+                }
+            }
+        }
+
+        if !self.depends_on.is_empty() {
+            writeln!(w, "  depends_on:")?;
+            for name in self.depends_on.iter() {
+                writeln!(w, "  - {name}")?;
+            }
+        }
+
         if !self.required_files.is_empty() {
             writeln!(w, "  required_files:")?;
             for file in self.required_files.iter() {
@@ -184,36 +1441,269 @@ impl Entry {
             }
         }
 
+        if !self.outputs.is_empty() {
+            writeln!(w, "  outputs:")?;
+            for file in self.outputs.iter() {
+                writeln!(w, "  - {file}")?;
+            }
+        }
+
+        if !self.exclude.is_empty() {
+            writeln!(w, "  exclude:")?;
+            for pattern in self.exclude.iter() {
+                writeln!(w, "  - {pattern}")?;
+            }
+        }
+
+        match &self.stdin {
+            Some(Stdin::File(file)) => writeln!(w, "  stdin: {file}")?,
+            Some(Stdin::Text(text)) => {
+                // Strip the final line break unless the text ends with one
+                let chomp = if text.ends_with('\n') { "" } else { "-" };
+                writeln!(w, "  stdin:")?;
+                writeln!(w, "    text: |{chomp}")?;
+                for line in text.lines() {
+                    writeln!(w, "      {}", line)?;
+                }
+            }
+            None => {}
+        }
+
+        if let Some(stdout_to) = &self.stdout_to {
+            writeln!(w, "  stdout_to: {stdout_to}")?;
+        }
+
+        if let Some(extra_hash) = &self.extra_hash {
+            writeln!(w, "  extra_hash: {extra_hash}")?;
+        }
+
+        if self.hash_mode {
+            writeln!(w, "  hash_mode: true")?;
+        }
+
+        if self.lexical_paths {
+            writeln!(w, "  lexical_paths: true")?;
+        }
+
+        if self.tracked_only {
+            writeln!(w, "  tracked_only: true")?;
+        }
+
+        if let Some(priority) = &self.priority {
+            writeln!(w, "  priority: {priority}")?;
+        }
+
+        if let Some(mutex) = &self.mutex {
+            writeln!(w, "  mutex: {mutex}")?;
+        }
+
+        if let Some(max_output_lines) = self.max_output_lines {
+            writeln!(w, "  max_output_lines: {max_output_lines}")?;
+        }
+
         if let Some(sha) = new_sha.or_else(|| self.sha.clone()) {
             writeln!(w, "  sha: {}", sha)?;
         }
 
+        if let Some(outputs_sha) = &self.outputs_sha {
+            writeln!(w, "  outputs_sha: {outputs_sha}")?;
+        }
+
         Ok(())
     }
 
     pub fn name(&self) -> &Option<String> {
         &self.name
     }
+
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.as_deref() == Some(name)
+    }
+
+    pub fn cmd(&self) -> &str {
+        &self.cmd
+    }
+
+    /// Hooks and command together, everything the entry runs
+    pub fn script(&self) -> String {
+        let scripts = [
+            self.before.as_deref(),
+            Some(self.cmd.as_str()),
+            self.after.as_deref(),
+            self.on_failure.as_deref(),
+        ];
+        scripts.into_iter().flatten().collect::<Vec<_>>().join("\n")
+    }
+
+    pub fn sha(&self) -> &Option<String> {
+        &self.sha
+    }
+
+    /// Record the sha of a successful run together with the digest of its
+    /// outputs, written out on the next dump
+    pub fn set_sha(&mut self, sha: Sha) {
+        self.sha = Some(sha);
+        self.outputs_sha = self.computed_outputs_sha.lock().unwrap().clone();
+    }
+
+    /// Forget the recorded sha so that the entry runs again
+    pub fn clear_sha(&mut self) {
+        self.sha = None;
+        self.outputs_sha = None;
+    }
+
+    pub fn rename(&mut self, name: &str) {
+        self.name = Some(name.into());
+    }
+
+    /// Point dependencies on a renamed entry to its new name
+    pub fn rename_dependency(&mut self, old: &str, new: &str) {
+        for name in self.depends_on.iter_mut() {
+            if *name == old {
+                *name = new.into();
+            }
+        }
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+            .as_deref()
+            .and_then(parse_priority)
+            .unwrap_or_default()
+    }
+
+    /// Group of related entries reported together and selected with
+    /// `--group`
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    pub fn section(&self) -> usize {
+        self.section
+    }
+
+    pub fn mutex(&self) -> Option<&str> {
+        self.mutex.as_deref()
+    }
+
+    pub fn depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
+
+    /// Time spent hashing and executing this entry so far
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
 }
 
+/// One line summary of an entry, or a multi-line description with `{:#}`
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // TODO: Display something more useful
-        write!(f, "{:?}", self)
+        let name = self.name.as_deref().unwrap_or("<unnamed>");
+        let files = self.files.len() + self.required_files.len();
+        let sha = self.sha.as_deref().map(|sha| sha.get(..8).unwrap_or(sha));
+
+        if !f.alternate() {
+            let cmd = self.cmd.lines().next().unwrap_or_default();
+            write!(f, "{name}: {cmd} ({files} files")?;
+            if let Some(sha) = sha {
+                write!(f, ", sha {sha}")?;
+            }
+            return write!(f, ")");
+        }
+
+        writeln!(f, "name: {name}")?;
+        writeln!(f, "cmd:")?;
+        for line in self.cmd.lines() {
+            writeln!(f, "  {line}")?;
+        }
+        writeln!(f, "files: {files}")?;
+        if !self.outputs.is_empty() || self.stdout_to.is_some() {
+            let outputs = self.outputs.len() + usize::from(self.stdout_to.is_some());
+            writeln!(f, "outputs: {outputs}")?;
+        }
+        write!(f, "sha: {}", sha.unwrap_or("none"))
     }
 }
 
 impl FromYaml for Entry {
     fn from_yaml(yaml: &Yaml) -> Result<Self> {
-        Ok(Self {
+        let keys = yaml.as_hash().ok_or(Error::ManifestMalformed)?.keys();
+        for key in keys.filter_map(Yaml::as_str) {
+            if !KEYS.iter().any(|(k, _)| *k == key) {
+                return Err(Error::UnknownKey {
+                    key: key.into(),
+                    suggestion: suggest::closest(key, KEYS.iter().map(|(k, _)| *k)),
+                });
+            }
+        }
+
+        let entry = Self {
             name: yaml["name"].as_str().map(String::from),
+            group: yaml["group"].as_str().map(String::from),
+            inherited_group: false,
+            section: 0,
             cmd: yaml["cmd"]
                 .as_str()
                 .map(String::from)
                 .ok_or(Error::MissingCmd)?,
+            before: yaml["before"].as_str().map(String::from),
+            after: yaml["after"].as_str().map(String::from),
+            on_failure: yaml["on_failure"].as_str().map(String::from),
             sha: yaml["sha"].as_str().map(String::from),
+            outputs_sha: yaml["outputs_sha"].as_str().map(String::from),
+            computed_outputs_sha: Mutex::default(),
+            accessed: Mutex::default(),
+            depends_on: str_vec(&yaml["depends_on"]),
             files: str_vec(&yaml["files"]),
             required_files: str_vec(&yaml["required_files"]),
-        })
+            outputs: str_vec(&yaml["outputs"]),
+            exclude: str_vec(&yaml["exclude"]),
+            stdin: stdin_value(&yaml["stdin"])?,
+            stdout_to: yaml["stdout_to"].as_str().map(String::from),
+            extra_hash: yaml["extra_hash"].as_str().map(String::from),
+            hash_mode: bool_value(&yaml["hash_mode"])?,
+            lexical_paths: bool_value(&yaml["lexical_paths"])?,
+            tracked_only: bool_value(&yaml["tracked_only"])?,
+            tracked: OnceLock::new(),
+            priority: yaml["priority"].as_str().map(String::from),
+            mutex: yaml["mutex"].as_str().map(String::from),
+            max_output_lines: yaml["max_output_lines"]
+                .as_str()
+                .map(str::parse)
+                .transpose()
+                .map_err(|_| Error::ManifestMalformed)?,
+            dir: PathBuf::new(),
+            manifest_path: None,
+            extra_input: Vec::new(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            debug_hash: false,
+            profile: Profile::default(),
+            fs_cache: Arc::default(),
+            git_cache: None,
+            algorithm: Arc::new(hash::Sha256),
+            cancel: CancellationToken::default(),
+        };
+
+        // Fail early on unset variables instead of silently using the
+        // unexpanded path
+        for file in entry
+            .files
+            .iter()
+            .chain(entry.required_files.iter())
+            .chain(entry.stdin_file())
+            .chain(entry.outputs.iter())
+            .chain(entry.stdout_to.iter())
+        {
+            expand::path(file)?;
+        }
+        if let Some(priority) = &entry.priority {
+            parse_priority(priority).ok_or(Error::ManifestMalformed)?;
+        }
+        for pattern in entry.exclude.iter() {
+            Pattern::new(pattern).map_err(|_| Error::InvalidPattern(pattern.clone()))?;
+        }
+
+        Ok(entry)
     }
 }
@@ -1,11 +1,22 @@
-use std::{fmt, io, string::FromUtf8Error};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+    time::Duration,
+};
 
 use thiserror::Error as ThisError;
 use strict_yaml_rust::{EmitError, ScanError};
 
+use crate::entry::ReifyFail;
+use crate::suggest::{self, did_you_mean};
+
 pub type Result<T> = core::result::Result<T, Error>;
 
+/// Errors carry paths, names and sources as data so callers can match on the
+/// cause instead of parsing messages
 #[derive(ThisError, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Can't load YAML from string")]
     LoadYaml(#[from] ScanError),
@@ -15,14 +26,128 @@ pub enum Error {
     MissingCmd,
     #[error("IO - {0}")]
     Io(#[from] io::Error),
-    #[error("Manifest file doesn't exist - '{0}'")]
-    ManifestFileDoesntExist(String),
+    #[error("IO on '{}' - {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+    #[error("{} (entry {index}) - {source}", name.as_deref().unwrap_or("<unnamed>"))]
+    Entry {
+        index: usize,
+        name: Option<String>,
+        source: Box<Error>,
+    },
+    #[error("{} - {source}", manifest.display())]
+    Manifest {
+        manifest: PathBuf,
+        source: Box<Error>,
+    },
+    #[error("Manifest file doesn't exist - '{}'", .0.display())]
+    ManifestFileDoesntExist(PathBuf),
     #[error("Problem converting from UTF-8")]
     ConvertUTF8(#[from] FromUtf8Error),
-    #[error("Invalid path - '{0}'")]
-    InvalidPath(String),
+    #[error("Manifest file isn't signed - '{}'", .0.display())]
+    MissingSignature(PathBuf),
+    #[error("Manifest signature isn't valid or trusted - '{}'", .0.display())]
+    InvalidSignature(PathBuf),
+    #[error("No entry named '{name}'{}", did_you_mean(suggestion))]
+    UnknownEntry {
+        name: String,
+        suggestion: Option<String>,
+    },
+    #[error("Unknown manifest key '{key}'{}", did_you_mean(suggestion))]
+    UnknownKey {
+        key: String,
+        suggestion: Option<String>,
+    },
+    #[error("Dependency cycle {}", .0.join(" → "))]
+    DependencyCycle(Vec<String>),
+    #[error("Entry named '{0}' already exists")]
+    DuplicateEntry(String),
+    #[error("Environment variable '{var}' is not set, used in '{path}'")]
+    UnsetVariable { var: String, path: String },
+    #[error("Invalid glob pattern - '{0}'")]
+    InvalidPattern(String),
+    #[error("Invalid path - '{}'", .0.display())]
+    InvalidPath(PathBuf),
     #[error("Cloudn't update config")]
     SerializeYaml(#[from] EmitError),
     #[error("Cloudn't dump entry")]
     DumpEntry(#[from] fmt::Error),
+    #[error("Can't find a data directory, set $XDG_DATA_HOME or $HOME")]
+    MissingDataDir,
+    #[error("Config file is malformed - '{}'", .0.display())]
+    ConfigMalformed(PathBuf),
+    #[error("Unknown config key '{key}'{}", did_you_mean(suggestion))]
+    UnknownConfigKey {
+        key: String,
+        suggestion: Option<String>,
+    },
+    #[error("Invalid value '{value}' for config key '{key}'")]
+    InvalidConfigValue { key: String, value: String },
+    #[error("Can't find a cache directory, set $XDG_CACHE_HOME or $HOME")]
+    MissingCacheDir,
+    #[error("Couldn't fetch '{0}'")]
+    FetchUrl(String),
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Timed out after {}s", .0.as_secs())]
+    TimedOut(Duration),
+    #[error("Couldn't serialize JSON")]
+    SerializeJson(#[from] serde_json::Error),
+    #[error("{hook} hook failed with exit code {code}")]
+    HookFailed { hook: &'static str, code: i32 },
+    #[error("{hook} hook not approved, run 'resha allow'")]
+    HookNotApproved { hook: &'static str },
+    #[error("Not in a git repository - '{}'", .0.display())]
+    NotInGitRepo(PathBuf),
+    #[error("{} failed - {source}", name.as_deref().unwrap_or("<unnamed>"))]
+    EntryFailed {
+        name: Option<String>,
+        source: ReifyFail,
+    },
+}
+
+impl Error {
+    /// No entry has the name, suggesting the closest of the names there are
+    pub fn unknown_entry<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> Self {
+        Self::UnknownEntry {
+            name: name.into(),
+            suggestion: suggest::closest(name, names),
+        }
+    }
+
+    /// Unknown config key, suggesting the closest of the known keys
+    pub fn unknown_config_key<'a>(key: &str, keys: impl IntoIterator<Item = &'a str>) -> Self {
+        Self::UnknownConfigKey {
+            key: key.into(),
+            suggestion: suggest::closest(key, keys),
+        }
+    }
+
+    /// Wrap an IO error with the file it happened on
+    pub fn file(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::File {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Add the entry that was being processed to an error
+    pub fn in_entry(self, index: usize, name: Option<&str>) -> Self {
+        Self::Entry {
+            index,
+            name: name.map(String::from),
+            source: Box::new(self),
+        }
+    }
+
+    /// Add the manifest that was being processed to an error, unless it
+    /// already names one
+    pub fn in_manifest(self, manifest: &Path) -> Self {
+        match self {
+            Self::Manifest { .. } => self,
+            _ => Self::Manifest {
+                manifest: manifest.into(),
+                source: Box::new(self),
+            },
+        }
+    }
 }
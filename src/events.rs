@@ -0,0 +1,100 @@
+use std::io::{self, Write};
+use std::mem;
+use std::sync::mpsc::Sender;
+
+/// How an entry finished
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The command ran and succeeded
+    Executed,
+    /// The sha didn't change so nothing ran
+    Unchanged,
+    /// The entry failed, e.g. its command exited with an error
+    Failed(String),
+    /// Hashing or running the entry failed
+    Error(String),
+}
+
+/// Progress of an entry being reified
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The entry is about to be checked and run if changed
+    Started { entry: Option<String> },
+    /// A line of output of the command without its line break
+    Output {
+        entry: Option<String>,
+        line: Vec<u8>,
+    },
+    /// The entry finished
+    Finished {
+        entry: Option<String>,
+        status: Status,
+    },
+}
+
+/// Receives the events of reified entries, e.g. to render a custom UI
+pub trait Observer {
+    fn event(&self, event: Event);
+}
+
+/// Events are sent to a channel, ignoring a receiver that hung up
+impl Observer for Sender<Event> {
+    fn event(&self, event: Event) {
+        let _ = self.send(event);
+    }
+}
+
+/// Writer passing output through while reporting every line to an observer
+pub struct Lines<'a> {
+    w: &'a mut dyn Write,
+    observer: &'a dyn Observer,
+    entry: Option<String>,
+    partial: Vec<u8>,
+}
+
+impl<'a> Lines<'a> {
+    pub fn new(w: &'a mut dyn Write, observer: &'a dyn Observer, entry: Option<String>) -> Self {
+        Self {
+            w,
+            observer,
+            entry,
+            partial: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self) {
+        let line = mem::take(&mut self.partial);
+        self.observer.event(Event::Output {
+            entry: self.entry.clone(),
+            line,
+        });
+    }
+
+    /// Report output that didn't end with a line break
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.partial.is_empty() {
+            self.emit();
+        }
+        self.w.flush()
+    }
+}
+
+impl Write for Lines<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.w.write_all(buf)?;
+        for chunk in buf.split_inclusive(|b| *b == b'\n') {
+            match chunk.strip_suffix(b"\n") {
+                Some(line) => {
+                    self.partial.extend_from_slice(line);
+                    self.emit();
+                }
+                None => self.partial.extend_from_slice(chunk),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
@@ -0,0 +1,370 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use duct::{cmd, Expression, ReaderHandle};
+
+use crate::cancel::CancellationToken;
+use crate::error::{Error, Result};
+
+/// Input of a command
+#[derive(Debug)]
+pub enum Input {
+    /// Nothing, commands waiting for input would otherwise hang
+    Null,
+    /// The stdin of resha
+    Inherit,
+    /// Contents of a file
+    File(PathBuf),
+    /// Inline bytes
+    Bytes(Vec<u8>),
+}
+
+/// Command of an entry ready to be executed
+#[derive(Debug)]
+pub struct Command<'a> {
+    /// Script run by the shell
+    pub script: String,
+    /// Directory of the manifest the command runs in
+    pub dir: &'a Path,
+    /// Variables added to the environment of the command
+    pub env: Vec<(&'static str, String)>,
+    pub stdin: Input,
+    /// File that receives stdout instead of `w`, stderr is still written
+    /// to `w`
+    pub stdout_to: Option<PathBuf>,
+    /// Kills the command when cancelled
+    pub cancel: CancellationToken,
+    /// Kills the command when it runs longer
+    pub timeout: Option<Duration>,
+}
+
+/// Runs the commands of entries, e.g. locally, in a container or on another
+/// host
+pub trait Executor: fmt::Debug + Send + Sync {
+    /// Run a command writing its output to `w` and return its exit code
+    fn execute(&self, command: &Command, w: &mut dyn Write) -> Result<i32>;
+}
+
+/// Quote a string as a single word for a POSIX shell
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Start a command in its own process group so the processes it started can
+/// be killed along with it
+#[cfg(unix)]
+fn own_process_group(expr: Expression) -> Expression {
+    use std::os::unix::process::CommandExt;
+
+    expr.before_spawn(|cmd| {
+        cmd.process_group(0);
+        Ok(())
+    })
+}
+
+#[cfg(not(unix))]
+fn own_process_group(expr: Expression) -> Expression {
+    expr
+}
+
+/// Kill a command started with `own_process_group` and everything it started,
+/// otherwise a child still holding its output open would keep it running
+#[cfg(unix)]
+fn kill_group(reader: &ReaderHandle) {
+    for pid in reader.pids() {
+        let group = format!("-{pid}");
+        let _ = cmd!("kill", "-KILL", "--", group).unchecked().run();
+    }
+    let _ = reader.kill();
+}
+
+#[cfg(not(unix))]
+fn kill_group(reader: &ReaderHandle) {
+    let _ = reader.kill();
+}
+
+/// Connect the input and output of a command and pass its output through as
+/// raw bytes so partial lines and non UTF-8 output are kept intact, the
+/// command is killed when cancelled or timed out
+fn stream(expr: Expression, command: &Command, w: &mut dyn Write) -> Result<i32> {
+    let expr = match &command.stdin {
+        Input::Null => expr.stdin_null(),
+        Input::Inherit => expr,
+        Input::File(path) => expr.stdin_path(path),
+        Input::Bytes(bytes) => expr.stdin_bytes(bytes.clone()),
+    };
+    let expr = match &command.stdout_to {
+        Some(path) => expr.stdout_path(path).stderr_to_stdout(),
+        None => expr.stderr_to_stdout(),
+    };
+    // Commands reading the terminal can't be moved out of its process group,
+    // only those that can time out are
+    let expr = match command.timeout {
+        Some(_) => own_process_group(expr),
+        None => expr,
+    };
+    // Non-zero exit codes are read from the status instead of failing reads
+    let reader = expr.unchecked().reader()?;
+    let done = AtomicBool::new(false);
+    let timed_out = AtomicBool::new(false);
+    let started = Instant::now();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                if command.cancel.is_cancelled() {
+                    let _ = reader.kill();
+                    break;
+                }
+                if command.timeout.is_some_and(|t| started.elapsed() >= t) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    kill_group(&reader);
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let mut buf = [0; 8 * 1024];
+        let copied = loop {
+            match (&reader).read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(n) => {
+                    if let Err(e) = w.write_all(&buf[..n]).and_then(|_| w.flush()) {
+                        break Err(e);
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        done.store(true, Ordering::SeqCst);
+        copied
+    })?;
+
+    if command.cancel.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    if let Some(timeout) = command.timeout.filter(|_| timed_out.load(Ordering::SeqCst)) {
+        return Err(Error::TimedOut(timeout));
+    }
+    // The command has been waited for once its output is closed
+    let status = reader.try_wait()?.map(|output| output.status);
+    Ok(status.map_or(1, exit_code))
+}
+
+/// Exit code of a finished command the way shells report it, `128` plus the
+/// signal for commands killed by one
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Runs commands with a local shell
+#[derive(Debug, Default)]
+pub struct Local {
+    /// Shell to run commands with, `bash` if not set
+    pub shell: Option<String>,
+}
+
+impl Executor for Local {
+    fn execute(&self, command: &Command, w: &mut dyn Write) -> Result<i32> {
+        let shell = self.shell.as_deref().unwrap_or("bash");
+        let mut expr = cmd!(shell, "-c", &command.script).dir(command.dir);
+        for (key, value) in command.env.iter() {
+            expr = expr.env(key, value);
+        }
+        stream(expr, command, w)
+    }
+}
+
+/// Runs commands locally under `strace`, logging the file system calls of the
+/// command and its children to `log`, Linux only
+#[derive(Debug)]
+pub struct Strace {
+    /// Shell to run commands with, `bash` if not set
+    pub shell: Option<String>,
+    pub log: PathBuf,
+}
+
+impl Executor for Strace {
+    fn execute(&self, command: &Command, w: &mut dyn Write) -> Result<i32> {
+        let shell = self.shell.as_deref().unwrap_or("bash");
+        let calls = "trace=open,openat,creat,rename,renameat,renameat2";
+        let mut expr = cmd!(
+            "strace",
+            "-f",
+            "-qq",
+            "-e",
+            calls,
+            "-o",
+            &self.log,
+            shell,
+            "-c",
+            &command.script
+        )
+        .dir(command.dir);
+        for (key, value) in command.env.iter() {
+            expr = expr.env(key, value);
+        }
+        stream(expr, command, w)
+    }
+}
+
+/// Quoted arguments in a line of `strace` output
+fn quoted(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+        let mut arg = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => arg.extend(chars.next()),
+                c => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+/// Files a command traced by `Strace` accessed
+#[derive(Debug, Default)]
+pub struct Accessed {
+    /// Files opened only for reading
+    pub read: Vec<PathBuf>,
+    /// Files opened for writing, created or renamed to
+    pub written: Vec<PathBuf>,
+    /// Files that couldn't be opened because they don't exist
+    pub missing: Vec<PathBuf>,
+}
+
+/// Files accessed according to a `Strace` log, relative paths are resolved
+/// against `dir`
+pub fn accessed_files(trace: &str, dir: &Path) -> Accessed {
+    let mut accessed = Accessed::default();
+    for line in trace.lines() {
+        // Lines start with the pid, failed calls didn't write anything
+        let call = line
+            .split_once(' ')
+            .map_or(line, |(_, call)| call.trim_start());
+        let args = quoted(call);
+        if call.contains(" = -1 ") {
+            if call.contains("ENOENT") && !call.starts_with("rename") {
+                accessed.missing.extend(args.first().map(|f| dir.join(f)));
+            }
+            continue;
+        }
+        let writes = ["O_WRONLY", "O_RDWR", "O_CREAT"];
+        if call.starts_with("rename") {
+            accessed.written.extend(args.last().map(|f| dir.join(f)));
+        } else if call.starts_with("creat(") || writes.iter().any(|flag| call.contains(flag)) {
+            accessed.written.extend(args.first().map(|f| dir.join(f)));
+        } else {
+            accessed.read.extend(args.first().map(|f| dir.join(f)));
+        }
+    }
+    accessed
+}
+
+/// Runs commands in a container with the manifest directory mounted at the
+/// same path
+#[derive(Debug)]
+pub struct Container {
+    /// Container engine, e.g. `docker` or `podman`
+    pub engine: String,
+    pub image: String,
+    /// Shell in the image to run commands with
+    pub shell: String,
+}
+
+impl Executor for Container {
+    fn execute(&self, command: &Command, w: &mut dyn Write) -> Result<i32> {
+        let dir = command.dir.to_string_lossy();
+        let mut args = vec![
+            "run".to_string(),
+            "--rm".into(),
+            "-i".into(),
+            "-v".into(),
+            format!("{dir}:{dir}"),
+            "-w".into(),
+            dir.to_string(),
+        ];
+        for (key, value) in command.env.iter() {
+            args.push("-e".into());
+            args.push(format!("{key}={value}"));
+        }
+        args.extend([
+            self.image.clone(),
+            self.shell.clone(),
+            "-c".into(),
+            command.script.clone(),
+        ]);
+        stream(duct::cmd(&self.engine, args), command, w)
+    }
+}
+
+/// Runs commands on another host over `ssh`, the manifest directory has to
+/// exist at the same path there
+#[derive(Debug)]
+pub struct Ssh {
+    /// Destination as given to `ssh`, e.g. `user@host`
+    pub host: String,
+    /// Shell on the host to run commands with
+    pub shell: String,
+}
+
+impl Executor for Ssh {
+    fn execute(&self, command: &Command, w: &mut dyn Write) -> Result<i32> {
+        let env = command
+            .env
+            .iter()
+            .map(|(key, value)| format!("{key}={}", quote(value)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let remote = format!(
+            "cd {} && {env} {} -c {}",
+            quote(&command.dir.to_string_lossy()),
+            self.shell,
+            quote(&command.script)
+        );
+        stream(cmd!("ssh", &self.host, remote), command, w)
+    }
+}
+
+/// Records the scripts of commands instead of running them, e.g. to test
+/// code reifying entries
+#[derive(Debug, Default)]
+pub struct Recorder {
+    scripts: Mutex<Vec<String>>,
+}
+
+impl Recorder {
+    /// Scripts of all commands executed so far
+    pub fn scripts(&self) -> Vec<String> {
+        self.scripts.lock().unwrap().clone()
+    }
+}
+
+impl Executor for Recorder {
+    fn execute(&self, command: &Command, _w: &mut dyn Write) -> Result<i32> {
+        self.scripts.lock().unwrap().push(command.script.clone());
+        Ok(0)
+    }
+}
@@ -0,0 +1,57 @@
+use std::env;
+
+use crate::error::{Error, Result};
+
+/// Expand a leading `~` to the home directory of the user
+pub fn tilde(path: &str) -> Result<String> {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => return Ok(path.into()),
+    };
+    let home = env::var("HOME").map_err(|_| Error::UnsetVariable {
+        var: "HOME".into(),
+        path: path.into(),
+    })?;
+    Ok(format!("{home}{rest}"))
+}
+
+/// Expand a leading `~`, `$VAR` and `${VAR}` in a path from a manifest with
+/// the value of the environment variable, `$` not followed by a variable name
+/// is kept as is
+pub fn path(path: &str) -> Result<String> {
+    let tilded = tilde(path)?;
+    let mut res = String::with_capacity(tilded.len());
+    let mut chars = tilded.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            res.push(c);
+            continue;
+        }
+
+        let braced = chars.next_if_eq(&'{').is_some();
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+        if braced && chars.next_if_eq(&'}').is_none() {
+            return Err(Error::InvalidPath(path.into()));
+        }
+
+        if name.is_empty() {
+            res.push('$');
+            if braced {
+                res.push_str("{}");
+            }
+            continue;
+        }
+
+        let value = env::var(&name).map_err(|_| Error::UnsetVariable {
+            var: name.clone(),
+            path: path.into(),
+        })?;
+        res.push_str(&value);
+    }
+
+    Ok(res)
+}
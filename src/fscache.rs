@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, FileType};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::error::{Error, Result};
+use crate::{limit, remote};
+
+type Listing = Arc<Vec<(PathBuf, FileType)>>;
+type Fetch = Arc<OnceLock<Option<PathBuf>>>;
+
+/// File system lookups shared by all entries of a run, only successful
+/// lookups are kept as commands may still create missing files, remote URLs
+/// are fetched at most once whether that succeeds or not
+#[derive(Debug, Default)]
+pub struct FsCache {
+    canonical: Mutex<HashMap<PathBuf, PathBuf>>,
+    existing: Mutex<HashSet<PathBuf>>,
+    dirs: Mutex<HashSet<PathBuf>>,
+    listings: Mutex<HashMap<PathBuf, Listing>>,
+    fetched: Mutex<HashMap<String, Fetch>>,
+}
+
+impl FsCache {
+    pub fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        if let Some(canonical) = self.canonical.lock().unwrap().get(path) {
+            return Ok(canonical.clone());
+        }
+        let canonical = dunce::canonicalize(path)?;
+        self.canonical
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), canonical.clone());
+        Ok(canonical)
+    }
+
+    /// Fail like `fs::metadata` if the path doesn't exist
+    pub fn check_exists(&self, path: &Path) -> io::Result<()> {
+        if self.existing.lock().unwrap().contains(path) {
+            return Ok(());
+        }
+        fs::metadata(path)?;
+        self.existing.lock().unwrap().insert(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        if self.dirs.lock().unwrap().contains(path) {
+            return true;
+        }
+        let is_dir = path.is_dir();
+        if is_dir {
+            self.dirs.lock().unwrap().insert(path.to_path_buf());
+        }
+        is_dir
+    }
+
+    /// Entries of a directory sorted by name, read once and shared by
+    /// manifest discovery and the globs and directories of all entries
+    fn read_dir(&self, dir: &Path) -> Listing {
+        if let Some(listing) = self.listings.lock().unwrap().get(dir) {
+            return listing.clone();
+        }
+        let permit = limit::open_file();
+        let mut listing = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|de| Some((de.path(), de.file_type().ok()?)))
+            .collect::<Vec<_>>();
+        drop(permit);
+        listing.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let listing = Arc::new(listing);
+        self.listings
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), listing.clone());
+        listing
+    }
+
+    /// Everything but directories below `root` without following symlinks,
+    /// descending at most `max_depth` levels and only into directories
+    /// `descend` accepts
+    pub fn walk(
+        &self,
+        root: &Path,
+        max_depth: usize,
+        descend: &dyn Fn(&Path) -> bool,
+    ) -> Vec<(PathBuf, FileType)> {
+        let mut found = Vec::new();
+        let mut dirs = vec![(root.to_path_buf(), 1)];
+        while let Some((dir, depth)) = dirs.pop() {
+            for (path, file_type) in self.read_dir(&dir).iter() {
+                if !file_type.is_dir() {
+                    found.push((path.clone(), *file_type));
+                } else if depth < max_depth && descend(path) {
+                    dirs.push((path.clone(), depth + 1));
+                }
+            }
+        }
+        found.sort_by(|(a, _), (b, _)| a.cmp(b));
+        found
+    }
+
+    /// Files below `root` like `walk`, including symlinks to files
+    pub fn walk_files(&self, root: &Path, max_depth: usize) -> Vec<PathBuf> {
+        self.walk(root, max_depth, &|_| true)
+            .into_iter()
+            .filter(|(path, file_type)| {
+                file_type.is_file()
+                    || file_type.is_symlink() && fs::metadata(path).is_ok_and(|m| m.is_file())
+            })
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Path of the cached copy of a remote URL, concurrent lookups of the same
+    /// URL wait for a single download
+    pub fn fetch(&self, url: &str) -> Result<PathBuf> {
+        let once = self
+            .fetched
+            .lock()
+            .unwrap()
+            .entry(url.to_string())
+            .or_default()
+            .clone();
+        let mut error = None;
+        let fetched = once.get_or_init(|| remote::fetch(url).map_err(|e| error = Some(e)).ok());
+        match (fetched, error) {
+            (Some(path), _) => Ok(path.clone()),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(Error::FetchUrl(url.into())),
+        }
+    }
+
+    /// Forget fetched URLs so the next lookup downloads them again, e.g. for
+    /// a new run of a long-lived process
+    pub fn forget_fetched(&self) {
+        self.fetched.lock().unwrap().clear();
+    }
+
+    /// Forget directory listings and lookups, e.g. after a command may have
+    /// created, removed or replaced files
+    pub fn forget_listings(&self) {
+        self.listings.lock().unwrap().clear();
+        self.canonical.lock().unwrap().clear();
+        self.existing.lock().unwrap().clear();
+        self.dirs.lock().unwrap().clear();
+    }
+}
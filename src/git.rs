@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use duct::cmd;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::error::{Error, Result};
+use crate::hash::{Algorithm, Sha256};
+
+/// Recorded sha and outputs digest of an entry
+#[derive(Clone, Debug)]
+pub struct Shas {
+    pub sha: String,
+    pub outputs_sha: Option<String>,
+}
+
+/// Shas of entries keyed by the git blob ids of their files, so entries whose
+/// files git reports as unmodified don't need to be hashed again
+#[derive(Debug)]
+pub struct GitCache {
+    /// Blob ids of tracked files that are unmodified in the working tree
+    blobs: HashMap<PathBuf, String>,
+    recorded: HashMap<String, Shas>,
+    /// Shas looked up or computed during this run, the only ones saved
+    used: Mutex<HashMap<String, Shas>>,
+    path: PathBuf,
+}
+
+/// Canonical top level directory of the repository containing `dir`
+pub fn repo_root(dir: &Path) -> Result<PathBuf> {
+    let root = cmd!("git", "rev-parse", "--show-toplevel")
+        .dir(dir)
+        .stderr_null()
+        .read()
+        .map_err(|_| Error::NotInGitRepo(dir.into()))?;
+    Ok(fs::canonicalize(root.trim())?)
+}
+
+/// Blob ids of the files in the index of the repository containing `dir`,
+/// leaving out files modified in the working tree
+fn clean_blobs(dir: &Path) -> Option<HashMap<PathBuf, String>> {
+    let root = repo_root(dir).ok()?;
+
+    let modified = cmd!("git", "diff-files", "--name-only", "-z")
+        .dir(&root)
+        .read()
+        .ok()?;
+    let modified = modified.split('\0').collect::<HashSet<_>>();
+
+    let staged = cmd!("git", "ls-files", "--stage", "-z")
+        .dir(&root)
+        .read()
+        .ok()?;
+    let blobs = staged
+        .split('\0')
+        .filter_map(|line| {
+            // <mode> <blob> <stage>\t<path>
+            let (info, path) = line.split_once('\t')?;
+            let mut info = info.split(' ');
+            let (mode, blob) = (info.next()?, info.next()?);
+            // Submodules aren't files
+            let file = mode != "160000" && !modified.contains(path);
+            file.then(|| (root.join(path), blob.to_string()))
+        })
+        .collect();
+    Some(blobs)
+}
+
+/// Canonical paths of the files git tracks in the repository containing
+/// `dir`
+pub fn tracked_files(dir: &Path) -> Result<HashSet<PathBuf>> {
+    let root = repo_root(dir)?;
+    let files = cmd!("git", "ls-files", "-z").dir(&root).read()?;
+    Ok(files
+        .split('\0')
+        .filter(|f| !f.is_empty())
+        .map(|f| root.join(f))
+        .collect())
+}
+
+/// Canonical paths of files with uncommitted changes, staged or not, and
+/// untracked files in the repository containing `dir`
+pub fn dirty_files(dir: &Path) -> Result<HashSet<PathBuf>> {
+    let root = repo_root(dir)?;
+    let args = ["status", "--porcelain", "-z", "--untracked-files=all"];
+    let status = duct::cmd("git", args).dir(&root).read()?;
+
+    let mut dirty = HashSet::new();
+    let mut entries = status.split('\0');
+    while let Some(entry) = entries.next() {
+        // XY <path>, renames and copies are followed by the original path
+        let (Some(status), Some(path)) = (entry.get(..2), entry.get(3..)) else {
+            continue;
+        };
+        if status.contains(['R', 'C']) {
+            dirty.extend(entries.next().map(|orig| root.join(orig)));
+        }
+        dirty.insert(root.join(path));
+    }
+    Ok(dirty)
+}
+
+impl GitCache {
+    /// Read the index of the repository containing the current directory and
+    /// shas recorded in `path`, `None` outside of a repository
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let Some(blobs) = clean_blobs(Path::new(".")) else {
+            debug!("not in a git repository, hashing all files");
+            return Ok(None);
+        };
+
+        let recorded = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str::<HashMap<String, Value>>(&text)?
+                .into_iter()
+                .filter_map(|(key, value)| {
+                    let sha = value["sha"].as_str()?.to_string();
+                    let outputs_sha = value["outputs_sha"].as_str().map(String::from);
+                    Some((key, Shas { sha, outputs_sha }))
+                })
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(Error::file(path)(e)),
+        };
+
+        Ok(Some(Self {
+            blobs,
+            recorded,
+            used: Mutex::default(),
+            path: path.into(),
+        }))
+    }
+
+    /// Key for the shas of an entry hashing `files` and `settings` with
+    /// `algorithm`, `None` if any file isn't tracked and unmodified
+    pub fn key(
+        &self,
+        algorithm: &dyn Algorithm,
+        files: &[PathBuf],
+        settings: &[&[u8]],
+    ) -> Option<String> {
+        let mut hasher = Sha256.hasher();
+        hasher.update(format!("{algorithm:?}").as_bytes());
+        for file in files {
+            let blob = self.blobs.get(file)?;
+            hasher.update(file.to_string_lossy().as_bytes());
+            hasher.update(blob.as_bytes());
+        }
+        for setting in settings {
+            hasher.update(&(setting.len() as u64).to_le_bytes());
+            hasher.update(setting);
+        }
+        Some(hasher.finish())
+    }
+
+    pub fn get(&self, key: &str) -> Option<Shas> {
+        let shas = self.recorded.get(key)?.clone();
+        self.used.lock().unwrap().insert(key.into(), shas.clone());
+        Some(shas)
+    }
+
+    pub fn insert(&self, key: String, shas: Shas) {
+        self.used.lock().unwrap().insert(key, shas);
+    }
+
+    /// Write the shas used by this run, dropping the rest
+    pub fn save(&self) -> Result<()> {
+        let used = self.used.lock().unwrap();
+        let value = used
+            .iter()
+            .map(|(key, shas)| {
+                let value = json!({"sha": shas.sha, "outputs_sha": shas.outputs_sha});
+                (key.clone(), value)
+            })
+            .collect::<serde_json::Map<_, _>>();
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_string(&value)?).map_err(Error::file(&self.path))?;
+        Ok(())
+    }
+}
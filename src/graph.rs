@@ -0,0 +1,105 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::entry::Entry;
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    New,
+    Active,
+    Done,
+}
+
+struct Graph<'a> {
+    entries: &'a [Entry],
+    index: HashMap<&'a str, usize>,
+    visits: Vec<Visit>,
+    stack: Vec<usize>,
+    order: Vec<usize>,
+}
+
+impl Graph<'_> {
+    fn name(&self, i: usize) -> String {
+        self.entries[i].name().clone().unwrap_or_default()
+    }
+
+    fn visit(&mut self, i: usize) -> Result<()> {
+        match self.visits[i] {
+            Visit::Done => return Ok(()),
+            Visit::Active => {
+                let start = self.stack.iter().position(|&j| j == i).unwrap_or(0);
+                let mut cycle = self.stack[start..]
+                    .iter()
+                    .map(|&j| self.name(j))
+                    .collect::<Vec<_>>();
+                cycle.push(self.name(i));
+                return Err(Error::DependencyCycle(cycle));
+            }
+            Visit::New => {}
+        }
+
+        self.visits[i] = Visit::Active;
+        self.stack.push(i);
+        let entries = self.entries;
+        for dep in entries[i].depends_on() {
+            let j = self.index[dep.as_str()];
+            self.visit(j)?;
+        }
+        self.stack.pop();
+        self.visits[i] = Visit::Done;
+        self.order.push(i);
+        Ok(())
+    }
+}
+
+/// Indices of the entries of a manifest in manifest order, entries with a
+/// higher priority first and entries of a group together
+pub fn manifest_order(entries: &[Entry]) -> Vec<usize> {
+    let mut order = (0..entries.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| Reverse(entries[i].priority()));
+
+    // Pull the entries of a group up to its first entry
+    let mut first = HashMap::new();
+    for (pos, &i) in order.iter().enumerate() {
+        if let Some(group) = entries[i].group() {
+            first.entry(group).or_insert(pos);
+        }
+    }
+    let mut keyed = order
+        .iter()
+        .enumerate()
+        .map(|(pos, &i)| (entries[i].group().map_or(pos, |g| first[g]), i))
+        .collect::<Vec<_>>();
+    keyed.sort_by_key(|&(key, _)| key);
+    keyed.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Indices of the entries of a manifest ordered so that every entry comes
+/// after the entries it depends on, keeping priority and manifest order
+/// otherwise
+pub fn topo_order(entries: &[Entry]) -> Result<Vec<usize>> {
+    let index = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| e.name().as_deref().map(|name| (name, i)))
+        .collect::<HashMap<_, _>>();
+
+    for dep in entries.iter().flat_map(Entry::depends_on) {
+        if !index.contains_key(dep.as_str()) {
+            return Err(Error::unknown_entry(dep, index.keys().copied()));
+        }
+    }
+
+    let mut graph = Graph {
+        entries,
+        index,
+        visits: vec![Visit::New; entries.len()],
+        stack: Vec::new(),
+        order: Vec::with_capacity(entries.len()),
+    };
+    for i in manifest_order(entries) {
+        graph.visit(i)?;
+    }
+    Ok(graph.order)
+}
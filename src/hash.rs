@@ -0,0 +1,57 @@
+use std::fmt;
+
+use sha2::Digest;
+
+/// Digest being computed over the files and settings of an entry
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+
+    /// Hex encoded digest of everything added
+    fn finish(self: Box<Self>) -> String;
+}
+
+/// Creates a hasher for every sha computed, plug in an implementation with
+/// `Entry::with_algorithm`
+pub trait Algorithm: fmt::Debug + Send + Sync {
+    fn hasher(&self) -> Box<dyn Hasher>;
+}
+
+/// SHA-256, the default used for shas recorded in manifests
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256;
+
+/// BLAKE3, faster on large inputs but gives different shas than SHA-256
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake3;
+
+impl Hasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        format!("{:x}", self.finalize())
+    }
+}
+
+impl Hasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> String {
+        self.finalize().to_hex().to_string()
+    }
+}
+
+impl Algorithm for Sha256 {
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(sha2::Sha256::new())
+    }
+}
+
+impl Algorithm for Blake3 {
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(blake3::Hasher::new())
+    }
+}
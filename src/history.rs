@@ -0,0 +1,83 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::entry::{Entry, ReifyResult, ReifySuccess};
+use crate::error::Result;
+use crate::project;
+
+fn path() -> Result<PathBuf> {
+    Ok(project::state_dir()?.join("history.jsonl"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Result of an entry that was checked during a run
+pub fn entry(manifest: &Path, e: &Entry, result: &ReifyResult, duration: Duration) -> Value {
+    let (status, sha) = match result {
+        Ok(ReifySuccess::ExecSuccess(sha)) => ("ran", Some(sha)),
+        Ok(ReifySuccess::Noop) => ("noop", e.sha().as_ref()),
+        Err(_) => ("failed", None),
+    };
+    json!({
+        "manifest": manifest.display().to_string(),
+        "entry": e.name(),
+        "status": status,
+        "duration_ms": duration.as_millis() as u64,
+        "sha": sha,
+    })
+}
+
+/// Append a JSON line with the results of every entry of a run to
+/// `.resha/history.jsonl`
+pub fn append(entries: Vec<Value>, success: bool) -> Result<()> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let record = json!({
+        "timestamp": now(),
+        "success": success,
+        "entries": entries,
+    });
+
+    // Write each record with a single call so concurrent runs don't
+    // interleave lines
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{record}\n").as_bytes())?;
+    Ok(())
+}
+
+/// The most recent runs, oldest first
+pub fn load(runs: usize) -> Result<Vec<Value>> {
+    let text = match fs::read_to_string(path()?) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let lines = text.lines().filter(|l| !l.trim().is_empty());
+    let records = lines
+        .map(serde_json::from_str)
+        .collect::<serde_json::Result<Vec<Value>>>()?;
+    let skip = records.len().saturating_sub(runs);
+    Ok(records.into_iter().skip(skip).collect())
+}
+
+/// How long ago a timestamp was in the largest fitting unit
+pub fn ago(timestamp: u64) -> String {
+    let secs = now().saturating_sub(timestamp);
+    match secs {
+        0..=59 => format!("{secs} seconds ago"),
+        60..=3599 => format!("{} minutes ago", secs / 60),
+        3600..=86399 => format!("{} hours ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
@@ -0,0 +1,54 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::HookFramework;
+
+const PRE_COMMIT: &str = "  - repo: local
+    hooks:
+      - id: resha
+        name: resha
+        entry: {entry}
+        language: system
+        pass_filenames: false
+        files: {files}
+";
+
+/// Path with forward slashes as used by git
+fn slashed(path: &Path) -> String {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn escape_regex(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Single quoted YAML scalar of a regular expression matching exactly
+/// `files`
+fn files_pattern(files: &[PathBuf]) -> String {
+    let alternatives = files
+        .iter()
+        .map(|f| escape_regex(&slashed(f)).replace('\'', "''"))
+        .collect::<Vec<_>>()
+        .join("|");
+    format!("'^({alternatives})$'")
+}
+
+/// Repository item running `entry` when any of `files` changes, to add to
+/// the `repos` of the framework's config
+pub fn item(framework: HookFramework, entry: &str, files: &[PathBuf]) -> String {
+    match framework {
+        HookFramework::PreCommit => PRE_COMMIT
+            .replace("{entry}", entry)
+            .replace("{files}", &files_pattern(files)),
+    }
+}
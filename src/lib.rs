@@ -0,0 +1,30 @@
+//! Keep your generated and versioned files in sync
+//!
+//! The library parses manifest entries, computes their shas and runs their
+//! commands, the `resha` binary adds discovery, reporting and subcommands on
+//! top of it.
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod build;
+pub mod cancel;
+pub mod entry;
+pub mod error;
+pub mod events;
+pub mod executor;
+pub mod expand;
+pub(crate) mod fscache;
+pub mod git;
+pub mod graph;
+pub mod hash;
+pub(crate) mod limit;
+pub mod manifest;
+pub(crate) mod output;
+pub(crate) mod profile;
+pub mod remote;
+pub(crate) mod suggest;
+
+pub use build::reify_manifest_for_build;
+pub use fscache::FsCache;
+pub use limit::{open_file, set_max_open_files, Permit};
+pub use profile::{Phase, Profile};
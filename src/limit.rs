@@ -0,0 +1,51 @@
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// Counting semaphore handing out a fixed number of permits
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Permit of a semaphore, returned when dropped
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until a permit is available and take it
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.released.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+static OPEN_FILES: OnceLock<Semaphore> = OnceLock::new();
+
+/// Limit how many files and directories are open for hashing and walking at
+/// the same time, unlimited unless set
+pub fn set_max_open_files(max: usize) {
+    let _ = OPEN_FILES.set(Semaphore::new(max.max(1)));
+}
+
+/// Wait for a free file handle, held until the permit is dropped
+pub fn open_file() -> Option<Permit<'static>> {
+    OPEN_FILES.get().map(Semaphore::acquire)
+}
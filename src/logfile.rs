@@ -0,0 +1,51 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+
+/// Number of log files from previous runs kept next to the current one
+const KEEP: usize = 5;
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(format!(".{n}"));
+    path.into()
+}
+
+/// Rotate log files from previous runs to `<path>.1`, `<path>.2`, ... and open
+/// a new log file for this run
+pub fn open(path: &Path) -> Result<File> {
+    for n in (1..KEEP).rev() {
+        let from = numbered(path, n);
+        if from.exists() {
+            fs::rename(&from, numbered(path, n + 1))?;
+        }
+    }
+    if path.exists() {
+        fs::rename(path, numbered(path, 1))?;
+    }
+
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// Duplicates everything written to the console into the log file
+pub struct Tee<'a, W: Write> {
+    pub console: W,
+    pub file: Option<&'a File>,
+}
+
+impl<W: Write> Write for Tee<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.console.write(buf)?;
+        if let Some(mut file) = self.file {
+            file.write_all(&buf[..n])?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.console.flush()
+    }
+}
@@ -1,51 +1,155 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::mem;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 
-use clap::Parser;
-use strict_yaml_rust::{StrictYaml as Yaml, StrictYamlLoader};
-use walkdir::WalkDir;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use tracing::{debug, error, warn, Level};
+use tracing_subscriber::{filter::Targets, fmt, prelude::*};
+use walkdir::{DirEntry, WalkDir};
 use pathdiff::diff_paths;
 
-mod entry;
-mod error;
-mod manifest;
+mod approval;
+mod audit;
+mod commands;
+mod completion;
+mod config;
+#[cfg(unix)]
+mod daemon;
+mod diagnostics;
+mod history;
+mod hook;
+mod logfile;
+mod man;
+mod niceness;
+mod notify;
+mod plugin;
+mod project;
+mod report;
+mod rpc;
+mod signature;
 
-use crate::entry::{Entry, FromYaml, ReifySuccess};
+use resha::executor::{Command as ExecCommand, Executor, Input, Local};
+use resha::{cancel, entry, error, expand, git, graph, manifest, FsCache, Phase, Profile};
+
+use crate::approval::Approvals;
+use crate::cancel::CancellationToken;
+use crate::entry::{Entry, ExecOptions, OutputCheck, ReifyFail, ReifySuccess};
 use crate::error::{Error, Result};
+use crate::git::GitCache;
+use crate::logfile::Tee;
+use crate::manifest::Manifest;
+use crate::report::{Outcome, Reporter, Style};
 
 /// Keep your generated and versioned files in sync
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None, trailing_var_arg = true)]
+#[derive(Parser, Clone, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    trailing_var_arg = true,
+    args_conflicts_with_subcommands = true,
+    group(
+        clap::ArgGroup::new("json_output")
+            .args(["dry_run", "print_inputs", "print_outputs"])
+            .multiple(true)
+    )
+)]
 struct Args {
-    /// Explicit manifest files to reify
-    #[arg(
-        allow_hyphen_values = true,
-        conflicts_with("recursive"),
-        conflicts_with("match")
-    )]
-    manifests: Vec<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Manifest file name to match
-    #[arg(long, default_value(".resha.yml"))]
-    r#match: String,
+    #[command(flatten)]
+    discovery: Discovery,
 
-    /// Recursively search for manifest files
-    #[arg(short, long, default_value_t = false)]
-    recursive: bool,
+    /// Log level of diagnostics printed to stderr
+    #[arg(long, value_enum, default_value_t = LogLevel::Warn)]
+    log_level: LogLevel,
+
+    /// Also write diagnostics and command output to a log file, rotated by run
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Number of manifests to reify concurrently
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
 
-    /// Skip entries after failed check
+    /// Skip remaining entries in a manifest after failed check
     #[arg(short, long, default_value_t = false)]
     fail_fast: bool,
 
+    /// Skip all remaining entries in all manifests after failed check
+    #[arg(short = 'F', long, default_value_t = false)]
+    fail_fast_all: bool,
+
     /// Dry run
     #[arg(short, long, default_value_t = false)]
     dry_run: bool,
 
+    /// Fail on manifest problems that are otherwise only warned about, e.g.
+    /// entries with a sha but no files
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// Run entries as usual but fail if any of them ran, i.e. generated files
+    /// were out of date
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    error_on_update: bool,
+
+    /// Sort entries by name when writing back manifests
+    #[arg(long, default_value_t = false)]
+    sort_entries: bool,
+
+    /// Run entries in manifest order or after the entries they depend on
+    #[arg(long, value_enum, default_value_t = Order::Manifest)]
+    order: Order,
+
+    /// Override a project config value for this run only, e.g. `timeout=600`,
+    /// can be given several times
+    #[arg(long, global = true, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    set: Vec<(String, String)>,
+
+    /// Only run the entries of a group, can be given several times
+    #[arg(long, value_name = "NAME")]
+    group: Vec<String>,
+
+    /// Show the command of stale entries in dry runs, twice for the full command
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Output format of dry runs and printed input and output files
+    #[arg(long, value_enum, default_value_t = Format::Tap, requires("json_output"))]
+    format: Format,
+
+    /// Only print the plan and failed entries in TAP output
+    #[arg(long, visible_alias = "quiet-tap", default_value_t = false)]
+    failures_only: bool,
+
+    /// Print plain ✓/✗ lines instead of TAP
+    #[arg(long, default_value_t = false)]
+    no_tap: bool,
+
+    /// Colorize output
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
     /// Print input files
     #[arg(short = 'i', long, default_value_t = false)]
     print_inputs: bool,
 
+    /// Print declared output files
+    #[arg(long, default_value_t = false)]
+    print_outputs: bool,
+
     /// Print manifest files
     #[arg(short = 'm', long, default_value_t = false)]
     print_manifests: bool,
@@ -54,192 +158,1478 @@ struct Args {
     #[arg(short, long, default_value_t = false)]
     only_print_reified: bool,
 
-    /// Hide execution output
+    /// Only print files from failed entries
+    #[arg(long, default_value_t = false, conflicts_with = "only_print_reified")]
+    only_print_failed: bool,
+
+    /// Print files relative to the current directory, their manifest, the
+    /// project root (containing `.resha/`) or the git repository root instead
+    /// of as absolute paths, reported manifests follow the chosen root
+    #[arg(long, value_enum, value_name = "BASE")]
+    relative_to: Option<RelativeTo>,
+
+    /// Separate printed files with NUL instead of line breaks
+    #[arg(short = '0', long, default_value_t = false)]
+    null: bool,
+
+    /// Hide execution output, unless the entry fails
     #[arg(short, long, default_value_t = false)]
     quiet: bool,
+
+    /// Only show the last lines of the hidden output of failed entries
+    #[arg(long, value_name = "N", requires = "quiet")]
+    quiet_tail: Option<usize>,
+
+    /// Only show the first and last lines of longer execution output
+    #[arg(long, value_name = "N")]
+    max_output_lines: Option<usize>,
+
+    /// Let commands read from stdin instead of /dev/null
+    #[arg(long, default_value_t = false)]
+    inherit_stdin: bool,
+
+    /// Size in bytes of the chunks files are read in when hashing
+    #[arg(long, value_name = "BYTES", default_value_t = entry::DEFAULT_BUFFER_SIZE)]
+    hash_buffer_size: usize,
+
+    /// Log the digest of every hashed file and the combined sha of each entry
+    /// to stderr
+    #[arg(long, default_value_t = false)]
+    debug_hash: bool,
+
+    /// Report time spent hashing, executing and writing manifests per entry
+    /// and for the whole run
+    #[arg(long, default_value_t = false)]
+    profile: bool,
+
+    /// Reuse shas of entries whose files git reports as unmodified instead of
+    /// hashing them again
+    #[arg(long, default_value_t = false)]
+    git_fast_path: bool,
+
+    /// Refuse to run entries whose inputs have uncommitted changes in git
+    #[arg(long, default_value_t = false)]
+    require_clean_tree: bool,
+
+    /// Maximum number of files and directories open for hashing and searching
+    /// at the same time
+    #[arg(long, global = true, value_name = "N")]
+    max_open_files: Option<usize>,
+
+    /// Make declared outputs read-only after generating them
+    #[arg(long, default_value_t = false)]
+    read_only_outputs: bool,
+
+    /// Fail entries whose outputs were modified since they were generated
+    /// instead of only warning
+    #[arg(long, default_value_t = false)]
+    fail_on_modified_outputs: bool,
+
+    /// Fail entries that succeed without generating their declared outputs
+    #[arg(long, value_enum, default_value_t = OutputCheck::Exist)]
+    check_outputs: OutputCheck,
+
+    /// Trace the files commands write with strace and report those not
+    /// listed in their entry (Linux only)
+    #[arg(long, default_value_t = false)]
+    trace_outputs: bool,
+
+    /// Trace the files commands read with strace and report those not listed
+    /// in their entry (Linux only)
+    #[arg(long, default_value_t = false)]
+    trace_inputs: bool,
+
+    /// Mix data read from stdin into the sha of every entry
+    #[arg(long, default_value_t = false, conflicts_with("inherit_stdin"))]
+    hash_stdin: bool,
+
+    /// Refuse to run commands that haven't been approved with `resha allow`
+    #[arg(long, default_value_t = false)]
+    require_approval: bool,
+
+    /// Verify manifest signatures against trusted keys before running any commands
+    #[arg(long, value_name = "FILE")]
+    trusted_keys: Option<PathBuf>,
+
+    /// Append a record of every executed entry to an audit log
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Run with this niceness, e.g. 19 for background runs
+    #[arg(long, allow_negative_numbers = true)]
+    nice: Option<i32>,
+
+    /// Run with this I/O scheduling class
+    #[arg(long, value_enum)]
+    ionice: Option<IoClass>,
+
+    /// Show a desktop notification when a run taking longer than this many
+    /// seconds finishes
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "10")]
+    notify: Option<u64>,
 }
 
-fn parse_entries(yaml: &Yaml) -> Result<Vec<Entry>> {
-    yaml.as_vec()
-        .ok_or(Error::ManifestMalformed)
-        .and_then(|ys| ys.iter().map(Entry::from_yaml).collect::<Result<Vec<_>>>())
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Approve the commands of all entries in the manifests
+    Allow {
+        #[command(flatten)]
+        discovery: Discovery,
+    },
+    /// Remove entries whose listed files all no longer exist
+    Prune {
+        #[command(flatten)]
+        discovery: Discovery,
+
+        /// Only list the entries that would be removed
+        #[arg(short, long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Delete declared outputs and clear the sha of their entries
+    Clean {
+        #[command(flatten)]
+        discovery: Discovery,
+    },
+    /// Delete outputs and run entries regardless of their recorded sha
+    Rebuild {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Names of the entries to rebuild
+        #[arg(required_unless_present("all"), conflicts_with("all"))]
+        entries: Vec<String>,
+
+        /// Rebuild all entries
+        #[arg(short, long, default_value_t = false)]
+        all: bool,
+    },
+    /// Record the current sha of entries without running them
+    Touch {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Names of the entries to touch
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+    /// Remove entries from their manifest
+    Remove {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Names of the entries to remove
+        #[arg(required = true)]
+        entries: Vec<String>,
+
+        /// Also delete the declared outputs of removed entries
+        #[arg(long, default_value_t = false)]
+        delete_outputs: bool,
+    },
+    /// Rename an entry and references to it keeping its recorded sha
+    Rename {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Current name of the entry
+        old: String,
+
+        /// New name of the entry
+        new: String,
+    },
+    /// Rewrite manifests in their canonical format
+    Fmt {
+        #[command(flatten)]
+        discovery: Discovery,
+
+        /// Sort entries by name
+        #[arg(long, default_value_t = false)]
+        sort: bool,
+    },
+    /// Print the names of all entries
+    List {
+        #[command(flatten)]
+        discovery: Discovery,
+
+        /// List entries in manifest order or after the entries they depend on
+        #[arg(long, value_enum, default_value_t = Order::Manifest)]
+        order: Order,
+
+        /// Separate names with NUL instead of line breaks
+        #[arg(short = '0', long, default_value_t = false)]
+        null: bool,
+    },
+    /// Print entries with their resolved files
+    Describe {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Names of the entries to describe
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+    /// Time hashing and running entries without updating their sha
+    Bench {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Names of the entries to benchmark
+        #[arg(required = true)]
+        entries: Vec<String>,
+
+        /// Number of times to run each entry
+        #[arg(short = 'n', long, default_value_t = 10)]
+        iterations: usize,
+    },
+    /// Run entries in a temporary directory holding only their declared
+    /// inputs and report files they access outside the declared inputs and
+    /// outputs (Linux only)
+    Hermetic {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Names of the entries to verify
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+    /// Validate a manifest and report problems with their line and column,
+    /// e.g. as diagnostics in an editor
+    CheckManifest {
+        /// Manifest file to check
+        file: PathBuf,
+
+        /// Print diagnostics as lines of text or a JSON array
+        #[arg(long, value_enum, default_value_t = DiagnosticFormat::Text)]
+        format: DiagnosticFormat,
+    },
+    /// Print the content of a manifest that its signature covers, the
+    /// manifest without recorded shas, to be signed for `--trusted-keys`
+    SignedContent {
+        /// Manifest file to print
+        file: PathBuf,
+    },
+    /// Print the recorded and current sha of every entry without running it
+    Shas {
+        #[command(flatten)]
+        discovery: Discovery,
+    },
+    /// Print a digest of the inputs of entries, all if none are named, to
+    /// use as a CI cache key for their outputs
+    CacheKey {
+        #[command(flatten)]
+        discovery: EntryDiscovery,
+
+        /// Names of the entries to include
+        entries: Vec<String>,
+    },
+    /// Print the results of recent runs
+    History {
+        /// Only show these entries
+        entries: Vec<String>,
+
+        /// Number of runs to show
+        #[arg(short = 'n', long, default_value_t = 10)]
+        runs: usize,
+    },
+    /// Serve `status`, `check` and `reify` requests for the discovered
+    /// manifests on a unix socket
+    #[cfg(unix)]
+    Daemon {
+        /// Socket to listen on, defaults to `.resha/daemon.sock`
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+    /// Let tools list, check and reify entries over a protocol on stdin and
+    /// stdout
+    #[command(group(clap::ArgGroup::new("protocol").required(true)))]
+    Serve {
+        #[command(flatten)]
+        discovery: Discovery,
+
+        /// Speak line delimited JSON-RPC 2.0
+        #[arg(long, group = "protocol")]
+        json_rpc: bool,
+
+        /// Refuse to run commands that haven't been approved with `resha allow`
+        #[arg(long, default_value_t = false)]
+        require_approval: bool,
+
+        /// Verify manifest signatures against trusted keys before running any commands
+        #[arg(long, value_name = "FILE")]
+        trusted_keys: Option<PathBuf>,
+    },
+    /// Print or install a hook checking the discovered manifests for a hook
+    /// framework, run from the root of the repository
+    Hook {
+        /// Hook framework to configure
+        framework: HookFramework,
+
+        #[command(flatten)]
+        discovery: Discovery,
+
+        /// Add the hook to the framework's config file instead of printing it
+        #[arg(long, default_value_t = false)]
+        install: bool,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to complete for
+        shell: Shell,
+    },
+    /// Print the man page
+    Man,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct Discovery {
+    /// Explicit manifest files
+    #[arg(
+        allow_hyphen_values = true,
+        conflicts_with("recursive"),
+        conflicts_with("match"),
+        conflicts_with("follow_symlinks"),
+        conflicts_with("hidden")
+    )]
+    manifests: Vec<PathBuf>,
+
+    #[command(flatten)]
+    search: Search,
+}
+
+/// Manifest discovery for subcommands taking entry names as arguments
+#[derive(clap::Args, Clone, Debug)]
+struct EntryDiscovery {
+    /// Explicit manifest file containing the entries
+    #[arg(
+        short = 'M',
+        long = "manifest",
+        value_name = "FILE",
+        conflicts_with("recursive"),
+        conflicts_with("match"),
+        conflicts_with("follow_symlinks"),
+        conflicts_with("hidden")
+    )]
+    manifests: Vec<PathBuf>,
+
+    #[command(flatten)]
+    search: Search,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct Search {
+    /// Manifest file name to match
+    #[arg(long, default_value(".resha.yml"))]
+    r#match: String,
+
+    /// Recursively search for manifest files
+    #[arg(short, long, default_value_t = false)]
+    recursive: bool,
+
+    /// Follow symlinked directories when searching for manifest files
+    #[arg(short = 'L', long, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Search hidden directories (e.g. .git) for manifest files
+    #[arg(long, default_value_t = false)]
+    hidden: bool,
+}
+
+impl Discovery {
+    fn find(&self) -> Result<Vec<PathBuf>> {
+        self.find_in(&FsCache::default())
+    }
+
+    /// Find manifests sharing directory listings with the rest of a run
+    fn find_in(&self, fs_cache: &FsCache) -> Result<Vec<PathBuf>> {
+        discover(&self.manifests, &self.search, fs_cache)
+    }
+}
+
+impl EntryDiscovery {
+    fn find(&self) -> Result<Vec<PathBuf>> {
+        discover(&self.manifests, &self.search, &FsCache::default())
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Order {
+    Manifest,
+    Topo,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Tap,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum RelativeTo {
+    Cwd,
+    Manifest,
+    Root,
+    Git,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DiagnosticFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum HookFramework {
+    PreCommit,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Level::ERROR,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Trace => Level::TRACE,
+        }
+    }
+}
+
+/// State shared between all manifests of a run
+#[derive(Default)]
+struct RunState {
+    failed: AtomicBool,
+    /// Whether any entry ran its command successfully
+    executed: AtomicBool,
+    stale: Mutex<Vec<Value>>,
+    /// Input files per entry printed with `--print-inputs --format json`
+    inputs: Mutex<Vec<Value>>,
+    /// Output files per entry printed with `--print-outputs --format json`
+    outputs: Mutex<Vec<Value>>,
+    /// Directory printed files are relative to, unless relative to their
+    /// manifest
+    path_base: Option<PathBuf>,
+    approvals: Option<Approvals>,
+    log_file: Option<File>,
+    project: project::Project,
+    extra_input: Vec<u8>,
+    mutexes: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    history: Mutex<Vec<Value>>,
+    profile: Profile,
+    fs_cache: Arc<FsCache>,
+    git_cache: Option<Arc<GitCache>>,
+    /// Files with uncommitted changes when `--require-clean-tree` is set
+    dirty: Option<HashSet<PathBuf>>,
+    cancel: CancellationToken,
+}
+
+impl RunState {
+    /// Lock shared by all entries with the same `mutex` key
+    fn mutex(&self, key: &str) -> Arc<Mutex<()>> {
+        self.mutexes
+            .lock()
+            .unwrap()
+            .entry(key.into())
+            .or_default()
+            .clone()
+    }
+}
+
+fn load_manifest(path: &Path) -> Result<Manifest> {
+    let manifest = Manifest::load(path).map_err(|e| e.in_manifest(&relative_path(path)))?;
+    for e in manifest.entries().iter().filter(|e| e.lists_no_files()) {
+        warn!(
+            entry = %e.name().as_deref().unwrap_or("<unnamed>"),
+            "{}: sha is recorded but no files are listed, only changes to cmd rerun the entry",
+            relative_path(path).display()
+        );
+    }
+    Ok(manifest)
 }
 
 fn parse_manifest(path: &Path) -> Result<Vec<Entry>> {
-    let yaml_str = fs::read_to_string(&path)?;
-    let docs = StrictYamlLoader::load_from_str(&yaml_str)?;
-    let yaml = docs.get(0).ok_or(Error::ManifestMalformed)?;
-    parse_entries(yaml)
+    load_manifest(path).map(Manifest::into_entries)
 }
 
-fn reify_manifest(
+fn parse_key_value(s: &str) -> core::result::Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{s}'"))
+}
+
+/// Path relative to the current working directory, used for display
+fn relative_path(path: &Path) -> PathBuf {
+    env::current_dir()
+        .ok()
+        .and_then(|wd| diff_paths(path, wd))
+        .unwrap_or_else(|| path.into())
+}
+
+/// Print a path for other programs to consume, keeping the raw bytes of
+/// non-UTF-8 paths on Unix
+fn write_path(args: &Args, out: &mut dyn Write, path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    out.write_all(std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()))?;
+    #[cfg(not(unix))]
+    out.write_all(path.to_string_lossy().as_bytes())?;
+
+    out.write_all(if args.null { b"\0" } else { b"\n" })?;
+    Ok(())
+}
+
+/// How an entry fared, deciding whether its files are printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Ran {
+    Reified,
+    Unchanged,
+    Failed,
+}
+
+fn print_files(
     args: &Args,
+    state: &RunState,
+    out: &mut dyn Write,
     path: &Path,
-    prev_success: bool,
-) -> Result<manifest::ReifyStatus> {
-    let print_files = |e: &Entry, success: bool| {
-        if args.print_inputs && (!args.only_print_reified || success) {
-            for path in e.all_files() {
-                println!("{}", path.display());
+    e: &Entry,
+    ran: Ran,
+) -> Result<()> {
+    if (args.only_print_reified && ran != Ran::Reified)
+        || (args.only_print_failed && ran != Ran::Failed)
+    {
+        return Ok(());
+    }
+    if args.print_inputs {
+        print_entry_files(args, state, &state.inputs, out, path, e, e.all_files())?;
+    }
+    if args.print_outputs {
+        print_entry_files(args, state, &state.outputs, out, path, e, e.outputs())?;
+    }
+    Ok(())
+}
+
+/// Print files of an entry, or collect them with the entry in `records` for
+/// the JSON report
+fn print_entry_files(
+    args: &Args,
+    state: &RunState,
+    records: &Mutex<Vec<Value>>,
+    out: &mut dyn Write,
+    path: &Path,
+    e: &Entry,
+    files: Vec<PathBuf>,
+) -> Result<()> {
+    if args.format == Format::Json {
+        let files = files
+            .iter()
+            .map(|f| printed_path(args, state, path, f).display().to_string())
+            .collect::<Vec<_>>();
+        let record = json!({
+            "manifest": reported_path(state, path).display().to_string(),
+            "entry": e.name(),
+            "files": files,
+        });
+        records.lock().unwrap().push(record);
+    } else {
+        for file in files {
+            write_path(args, out, &printed_path(args, state, path, &file))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_cmd(args: &Args, reporter: &Reporter, out: &mut dyn Write, e: &Entry) -> Result<()> {
+    let cmd = e.cmd().trim_end();
+    match args.verbose {
+        0 => {}
+        1 => {
+            let first = cmd.lines().next().unwrap_or_default();
+            reporter.diagnostic(out, &format!("cmd: {first}"))?;
+        }
+        _ => {
+            reporter.diagnostic(out, "cmd:")?;
+            for line in cmd.lines() {
+                reporter.diagnostic(out, &format!("  {line}"))?;
             }
         }
+    }
+    Ok(())
+}
+
+/// File as printed, relative to the base chosen with `--relative-to`
+fn printed_path(args: &Args, state: &RunState, manifest: &Path, file: &Path) -> PathBuf {
+    let base = match args.relative_to {
+        Some(RelativeTo::Manifest) => manifest.parent(),
+        _ => state.path_base.as_deref(),
     };
+    base.and_then(|base| diff_paths(file, base))
+        .unwrap_or_else(|| file.into())
+}
 
-    let print_tap = !args.print_inputs && !args.print_manifests;
+/// Manifest as reported, relative to the current directory unless
+/// `--relative-to` chose another base for all manifests
+fn reported_path(state: &RunState, manifest: &Path) -> PathBuf {
+    state
+        .path_base
+        .as_deref()
+        .and_then(|base| diff_paths(manifest, base))
+        .unwrap_or_else(|| relative_path(manifest))
+}
 
-    // Change working directory to manifest files dir
-    let old_wd = env::current_dir()?;
-    let wd = path
-        .parent()
-        .ok_or_else(|| Error::InvalidPath(path.display().to_string()))?;
-    env::set_current_dir(wd)?;
+/// Show the hidden output of a failed entry, or only its last lines
+fn print_captured(
+    args: &Args,
+    reporter: &Reporter,
+    out: &mut dyn Write,
+    captured: &[u8],
+) -> Result<()> {
+    let text = String::from_utf8_lossy(captured);
+    let lines = text.lines().collect::<Vec<_>>();
+    let skip = match args.quiet_tail {
+        Some(n) => lines.len().saturating_sub(n),
+        None => 0,
+    };
+    for line in &lines[skip..] {
+        reporter.diagnostic(out, line)?;
+    }
+    Ok(())
+}
 
-    let entries = parse_manifest(&path)?;
+fn use_color(args: &Args) -> bool {
+    match args.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    }
+}
 
-    let mut success = prev_success;
-    let mut updated = false;
-    let mut output = String::new();
+fn reporter(args: &Args) -> Reporter {
+    let style = if args.print_inputs
+        || args.print_outputs
+        || args.print_manifests
+        || args.format == Format::Json
+    {
+        Style::Hidden
+    } else if args.no_tap {
+        Style::Plain
+    } else {
+        Style::Tap
+    };
+
+    Reporter {
+        style,
+        color: use_color(args),
+        failures_only: args.failures_only,
+    }
+}
+
+fn reify_manifest(
+    args: &Args,
+    path: &Path,
+    mut manifest: Manifest,
+    state: &RunState,
+    out: &mut dyn Write,
+    log: &mut dyn Write,
+) -> Result<manifest::ReifyStatus> {
+    let reporter = reporter(args);
+    let exec_options = ExecOptions {
+        inherit_stdin: args.inherit_stdin,
+        shell: state.project.shell.clone(),
+        max_output_lines: args.max_output_lines.or(state.project.max_output_lines),
+        timeout: state.project.timeout,
+        read_only_outputs: args.read_only_outputs,
+        fail_on_modified_outputs: args.fail_on_modified_outputs,
+        check_outputs: args.check_outputs,
+        trace: args.trace_inputs || args.trace_outputs,
+        dry_run: args.dry_run,
+        ..Default::default()
+    };
+
+    let entries = mem::take(manifest.entries_mut())
+        .into_iter()
+        .map(|e| {
+            e.with_extra_input(&state.extra_input)
+                .with_buffer_size(args.hash_buffer_size)
+                .with_debug_hash(args.debug_hash)
+                .with_fs_cache(&state.fs_cache)
+                .with_cancellation(&state.cancel)
+        })
+        .map(|e| match &state.git_cache {
+            Some(git_cache) => e.with_git_cache(git_cache),
+            None => e,
+        })
+        .collect::<Vec<_>>();
 
-    if print_tap {
-        let path = diff_paths(path, &old_wd).unwrap_or_else(|| path.into());
-        println!("1..{}  # manifest {}", entries.len(), path.display());
+    let mut order = match args.order {
+        Order::Manifest => graph::manifest_order(&entries),
+        Order::Topo => graph::topo_order(&entries)?,
+    };
+    if !args.group.is_empty() {
+        order.retain(|&i| {
+            let group = entries[i].group();
+            args.group.iter().any(|g| Some(g.as_str()) == group)
+        });
     }
 
-    for (i, e) in entries.iter().enumerate() {
+    let mut success = true;
+    let mut updated = false;
+    // Entries are written back in manifest order whatever order they run in
+    let mut shas = vec![None; entries.len()];
+
+    reporter.plan(out, order.len(), &reported_path(state, path))?;
+
+    let mut group = None;
+    for (i, &index) in order.iter().enumerate() {
         let i = i + 1;
+        let e = &entries[index];
         let name = e.name().clone().unwrap_or("<unnamed>".into());
+        let mut new_sha = None;
 
-        if args.fail_fast && !success {
-            if !args.dry_run {
-                e.dump(&mut output, None)?;
+        // Dependencies can split up groups, headers would repeat
+        let new_group = e.group().filter(|&g| Some(g) != group);
+        if let Some(name) = new_group.filter(|_| args.order == Order::Manifest) {
+            reporter.group(out, name)?;
+        }
+        group = e.group();
+
+        let mut reify_entry = || -> Result<()> {
+            let fail_fast = (args.fail_fast && !success)
+                || (args.fail_fast_all && state.failed.load(Ordering::SeqCst));
+
+            if fail_fast {
+                print_files(args, state, out, path, e, Ran::Unchanged)?;
+                reporter.entry(out, i, &name, &Outcome::Skip("fail fast"))?;
+                return Ok(());
             }
-            print_files(e, false);
-            if print_tap {
-                println!("ok {i} - {name}  # SKIP (fail fast)");
+
+            if state.cancel.is_cancelled() {
+                reporter.entry(out, i, &name, &Outcome::Skip("cancelled"))?;
+                return Ok(());
             }
-            continue;
-        }
 
-        if args.dry_run {
-            match e.dry_run()? {
-                Ok(_) => {
-                    updated = true;
-                    print_files(e, true);
-                    if print_tap {
-                        println!("ok {i} - {name}  # dry run");
+            // Warned about when the manifest is loaded, only fails with --strict
+            if args.strict && e.lists_no_files() {
+                success = false;
+                state.failed.store(true, Ordering::SeqCst);
+                print_files(args, state, out, path, e, Ran::Failed)?;
+                let fail = ReifyFail::NoFilesListed.to_string();
+                reporter.entry(out, i, &name, &Outcome::NotOk(fail))?;
+                return Ok(());
+            }
+
+            if args.dry_run {
+                match e.dry_run()? {
+                    Ok(_) => {
+                        updated = true;
+                        print_files(args, state, out, path, e, Ran::Reified)?;
+                        reporter.entry(out, i, &name, &Outcome::Ok("dry run"))?;
                     }
-                }
-                Err(fail) => {
-                    success = false;
-                    print_files(e, false);
-                    if print_tap {
-                        println!("not ok {i} - {name}  # {fail}");
+                    Err(fail) => {
+                        success = false;
+                        state.failed.store(true, Ordering::SeqCst);
+                        if args.format == Format::Json {
+                            let inputs = e
+                                .all_files()
+                                .iter()
+                                .map(|f| printed_path(args, state, path, f).display().to_string())
+                                .collect::<Vec<_>>();
+                            let stale = json!({
+                                "manifest": reported_path(state, path).display().to_string(),
+                                "entry": e.name(),
+                                "group": e.group(),
+                                "index": i,
+                                "recorded_sha": e.sha(),
+                                "computed_sha": e.calc_sha()?,
+                                "cmd": e.cmd(),
+                                "inputs": inputs,
+                            });
+                            state.stale.lock().unwrap().push(stale);
+                        }
+                        print_files(args, state, out, path, e, Ran::Failed)?;
+                        reporter.entry(out, i, &name, &Outcome::NotOk(fail.to_string()))?;
+                        print_cmd(args, &reporter, out, e)?;
                     }
                 }
+                return Ok(());
             }
-            continue;
-        }
-
-        let reify_status = if !args.quiet {
-            e.reify(&mut std::io::stderr())
-        } else {
-            e.reify(&mut std::io::sink())
-        };
 
-        match reify_status? {
-            Ok(ReifySuccess::ExecSuccess(sha)) => {
-                updated = true;
-                e.dump(&mut output, Some(sha))?;
-                print_files(e, true);
-                if print_tap {
-                    println!("ok {i} - {name}");
+            let approved = match &state.approvals {
+                Some(approvals) => {
+                    approvals.is_approved(path, &state.project.approval_text(&e.script()))
                 }
+                None => true,
+            };
+
+            let clean = match &state.dirty {
+                Some(dirty) => !e.inputs().iter().any(|f| dirty.contains(f)),
+                None => true,
+            };
+
+            // Entries sharing a mutex never run at the same time, even in
+            // different manifests
+            let mutex = e.mutex().map(|key| state.mutex(key));
+            let _guard = mutex.as_ref().map(|m| m.lock().unwrap());
+
+            // Quiet output is kept to explain failures
+            let mut captured = Vec::new();
+            let started = Instant::now();
+            let reify_status = if !approved {
+                // Only refuse entries that would actually run
+                e.dry_run()
+                    .map(|res| res.map_err(|_| ReifyFail::NotApproved))
+            } else if !clean {
+                // Shas of uncommitted inputs would be written back
+                e.dry_run()
+                    .map(|res| res.map_err(|_| ReifyFail::DirtyInputs))
+            } else if !args.quiet {
+                e.reify(log, &exec_options)
+            } else {
+                e.reify(&mut captured, &exec_options)
+            }?;
+
+            if let Some(audit_log) = &args.audit_log {
+                audit::append(audit_log, path, e, &reify_status, started.elapsed())?;
             }
-            Ok(ReifySuccess::Noop) => {
-                e.dump(&mut output, None)?;
-                print_files(e, false);
-                if print_tap {
-                    println!("ok {i} - {name}  # noop");
+            let record = history::entry(path, e, &reify_status, started.elapsed());
+            state.history.lock().unwrap().push(record);
+
+            match reify_status {
+                Ok(ReifySuccess::ExecSuccess(sha)) => {
+                    updated = true;
+                    state.executed.store(true, Ordering::SeqCst);
+                    new_sha = Some(sha);
+                    print_files(args, state, out, path, e, Ran::Reified)?;
+                    reporter.entry(out, i, &name, &Outcome::Ok(""))?;
+                    if args.trace_inputs {
+                        for path in e.undeclared_inputs() {
+                            let message = format!("undeclared input: {}", path.display());
+                            reporter.diagnostic(out, &message)?;
+                        }
+                    }
+                    if args.trace_outputs {
+                        for path in e.undeclared_outputs() {
+                            let message = format!("undeclared output: {}", path.display());
+                            reporter.diagnostic(out, &message)?;
+                        }
+                    }
                 }
-            }
-            Err(fail) => {
-                success = false;
-                e.dump(&mut output, None)?;
-                print_files(e, false);
-                if print_tap {
-                    println!("not ok {i} - {name}  # {fail}");
+                Ok(ReifySuccess::Noop) => {
+                    print_files(args, state, out, path, e, Ran::Unchanged)?;
+                    reporter.entry(out, i, &name, &Outcome::Ok("noop"))?;
+                }
+                Err(fail) => {
+                    success = false;
+                    state.failed.store(true, Ordering::SeqCst);
+                    print_files(args, state, out, path, e, Ran::Failed)?;
+                    reporter.entry(out, i, &name, &Outcome::NotOk(fail.to_string()))?;
+                    print_captured(args, &reporter, out, &captured)?;
                 }
             }
+            Ok(())
+        };
+        reify_entry().map_err(|err| err.in_entry(i, e.name().as_deref()))?;
+        shas[index] = new_sha;
+
+        if args.profile {
+            reporter.diagnostic(out, &format!("profile: {}", e.profile()))?;
         }
+        state.profile.merge(e.profile());
     }
 
-    if args.print_manifests && (!args.only_print_reified || updated) {
-        println!("{}", path.display());
+    *manifest.entries_mut() = entries;
+    for (e, sha) in manifest.entries_mut().iter_mut().zip(shas) {
+        if let Some(sha) = sha {
+            e.set_sha(sha);
+        }
+    }
+    if args.sort_entries {
+        updated |= manifest.sort_entries();
     }
 
-    // Change back work directory to before
-    env::set_current_dir(old_wd)?;
+    let print_manifest = if args.only_print_reified {
+        updated
+    } else if args.only_print_failed {
+        !success
+    } else {
+        true
+    };
+    if args.print_manifests && print_manifest {
+        write_path(args, out, &printed_path(args, state, path, path))?;
+    }
 
-    Ok(manifest::ReifyStatus { output, success, updated })
+    Ok(manifest::ReifyStatus { manifest, updated })
 }
 
-fn find_manifests(root: &Path, name: &String, recursive: bool) -> Vec<PathBuf> {
-    let mut res = Vec::new();
+fn find_manifests(
+    root: &Path,
+    name: &str,
+    recursive: bool,
+    follow_symlinks: bool,
+    hidden: bool,
+    exclude_dirs: &[PathBuf],
+    fs_cache: &FsCache,
+) -> Vec<PathBuf> {
+    let max_depth = if recursive { usize::MAX } else { 1 };
+    let skip_dir = |dir: &Path| {
+        let skip = !hidden
+            && dir
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+        if skip {
+            debug!("skipping hidden directory {}", dir.display());
+        }
+        let excluded = !exclude_dirs.is_empty()
+            && dunce::canonicalize(dir).is_ok_and(|dir| exclude_dirs.contains(&dir));
+        if excluded {
+            debug!("skipping excluded directory {}", dir.display());
+        }
+        skip || excluded
+    };
 
-    let name = name.as_str();
-    let walk = WalkDir::new(root);
-    let walk = if recursive { walk } else { walk.max_depth(1) };
-    for de in walk.into_iter().filter_map(|de| {
-        let de = de.ok()?;
-        let pred = de.file_name() == name && de.metadata().ok()?.is_file();
-        pred.then_some(de)
-    }) {
-        res.push(de.path().to_path_buf());
-    }
+    let found = if follow_symlinks {
+        // WalkDir detects symlink loops itself and yields them as errors,
+        // which are skipped below, it keeps a single directory open while
+        // holding the permit
+        let _permit = resha::open_file();
+        WalkDir::new(root)
+            .follow_links(true)
+            .max_depth(max_depth)
+            .max_open(1)
+            .into_iter()
+            .filter_entry(|de| de.depth() == 0 || !de.file_type().is_dir() || !skip_dir(de.path()))
+            .filter_map(|de| de.map_err(|e| debug!("skipping {e}")).ok())
+            .filter(|de| !de.file_type().is_dir())
+            .map(DirEntry::into_path)
+            .collect::<Vec<_>>()
+    } else {
+        // Share directory listings with the globs and directories of entries
+        let root = dunce::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        fs_cache
+            .walk(&root, max_depth, &|dir| !skip_dir(dir))
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    };
 
-    res
+    found
+        .into_iter()
+        .filter(|path| {
+            path.file_name().is_some_and(|n| n == name)
+                && fs::metadata(path).is_ok_and(|m| m.is_file())
+        })
+        .inspect(|path| debug!("found manifest {}", path.display()))
+        .collect()
 }
 
-fn start(args: &Args) -> Result<bool> {
-    let files = if args.manifests.len() > 0 {
-        args.manifests.clone()
+fn discover(manifests: &[PathBuf], search: &Search, fs_cache: &FsCache) -> Result<Vec<PathBuf>> {
+    let files = if !manifests.is_empty() {
+        manifests
+            .iter()
+            .map(|p| match p.to_str() {
+                Some(s) => expand::tilde(s).map(PathBuf::from),
+                None => Ok(p.clone()),
+            })
+            .collect::<Result<Vec<_>>>()?
     } else {
-        find_manifests(Path::new("."), &args.r#match, args.recursive)
+        find_manifests(
+            Path::new("."),
+            &search.r#match,
+            search.recursive,
+            search.follow_symlinks,
+            search.hidden,
+            &project::load()?.exclude_dirs,
+            fs_cache,
+        )
     };
 
-    let files = files
+    let mut files = files
         .iter()
         .map(|p| {
-            p.canonicalize()
-                .map_err(|_| Error::ManifestFileDoesntExist(p.display().to_string()))
+            // Avoid `\\?\` prefixed paths on Windows which break sorting and
+            // relative paths
+            dunce::canonicalize(p).map_err(|_| Error::ManifestFileDoesntExist(p.clone()))
         })
         .collect::<Result<Vec<_>>>()?;
 
-    let mut success = true;
+    // The same manifest can be reached through several symlinked paths, only
+    // reify it once
+    let mut seen = HashSet::new();
+    files.retain(|p| seen.insert(p.clone()));
+
+    Ok(files)
+}
+
+fn start(args: &Args, log_file: Option<File>) -> Result<bool> {
+    project::set_overrides(args.set.clone());
+    if let Some(max) = args.max_open_files {
+        resha::set_max_open_files(max);
+    }
+    match &args.command {
+        Some(Command::Allow { discovery }) => commands::allow(discovery),
+        Some(Command::Prune { discovery, dry_run }) => commands::prune(discovery, *dry_run),
+        Some(Command::Clean { discovery }) => commands::clean(discovery),
+        Some(Command::Rebuild {
+            discovery,
+            entries,
+            all,
+        }) => commands::rebuild(discovery, entries, *all),
+        Some(Command::Touch { discovery, entries }) => commands::touch(discovery, entries),
+        Some(Command::Remove {
+            discovery,
+            entries,
+            delete_outputs,
+        }) => commands::remove(discovery, entries, *delete_outputs),
+        Some(Command::Rename {
+            discovery,
+            old,
+            new,
+        }) => commands::rename(discovery, old, new),
+        Some(Command::Fmt { discovery, sort }) => commands::fmt(discovery, *sort),
+        Some(Command::List {
+            discovery,
+            order,
+            null,
+        }) => commands::list(discovery, *order, *null),
+        Some(Command::Describe { discovery, entries }) => commands::describe(discovery, entries),
+        Some(Command::Bench {
+            discovery,
+            entries,
+            iterations,
+        }) => commands::bench(discovery, entries, *iterations),
+        Some(Command::Hermetic { discovery, entries }) => commands::hermetic(discovery, entries),
+        Some(Command::CheckManifest { file, format }) => commands::check_manifest(file, *format),
+        Some(Command::SignedContent { file }) => commands::signed_content(file),
+        Some(Command::Shas { discovery }) => commands::shas(discovery),
+        Some(Command::CacheKey { discovery, entries }) => commands::cache_key(discovery, entries),
+        Some(Command::History { entries, runs }) => commands::history(entries, *runs),
+        #[cfg(unix)]
+        Some(Command::Daemon { socket }) => {
+            let socket = match socket {
+                Some(socket) => socket.clone(),
+                None => project::state_dir()?.join("daemon.sock"),
+            };
+            daemon::serve(args, &socket)
+        }
+        Some(Command::Serve {
+            discovery,
+            json_rpc: true,
+            require_approval,
+            trusted_keys,
+        }) => rpc::serve(discovery, *require_approval, trusted_keys.as_deref()),
+        Some(Command::Serve { .. }) => unreachable!("clap requires a protocol"),
+        Some(Command::Hook {
+            framework,
+            discovery,
+            install,
+        }) => commands::hook(*framework, discovery, *install),
+        Some(Command::Completions { shell }) => commands::completions(*shell),
+        Some(Command::Man) => man::render(&mut io::stdout()).map(|_| true),
+        None => reify(args, log_file),
+    }
+}
+
+fn reify(args: &Args, log_file: Option<File>) -> Result<bool> {
+    let started = Instant::now();
+    niceness::lower(args.nice, args.ionice);
+
+    let fs_cache = Arc::new(FsCache::default());
+    let files = args.discovery.find_in(&fs_cache)?;
+
+    // Finish the report on the first interrupt, stop right away on the second
+    let cancel = CancellationToken::default();
+    let interrupted = cancel.clone();
+    let handler = ctrlc::set_handler(move || {
+        if interrupted.is_cancelled() {
+            std::process::exit(130);
+        }
+        interrupted.cancel();
+    });
+    if let Err(e) = handler {
+        warn!("can't handle interrupts - {e}");
+    }
+
+    let jobs = args.jobs.min(files.len()).max(1);
+    let state = run_state(args, &files, fs_cache, log_file, cancel)?;
+
+    // Setup shared by all entries, e.g. installing dependencies
+    let mut log = Tee {
+        console: io::stderr(),
+        file: state.log_file.as_ref(),
+    };
+    run_before_all(args, &state, &mut log)?;
+
+    // Stream output directly when running serially, otherwise every manifest
+    // buffers its output so that it can be emitted in one piece
+    if jobs == 1 {
+        for path in files.iter() {
+            let mut out = Tee {
+                console: io::stdout(),
+                file: state.log_file.as_ref(),
+            };
+            let mut log = Tee {
+                console: io::stderr(),
+                file: state.log_file.as_ref(),
+            };
+            isolate_manifest(args, path, load_manifest(path), &state, &mut out, &mut log);
+        }
+        return finish(args, state, started);
+    }
+
+    // Start manifests with high priority entries first so long running
+    // generators don't end up last, manifests that fail to load are reported
+    // when their turn comes
+    let mut manifests = files
+        .iter()
+        .map(|path| (path, load_manifest(path)))
+        .collect::<Vec<_>>();
+    manifests.sort_by_cached_key(|(_, manifest)| {
+        let priority = manifest
+            .as_ref()
+            .ok()
+            .and_then(|m| m.entries().iter().map(Entry::priority).max());
+        Reverse(priority.unwrap_or_default())
+    });
+
+    let queue = Mutex::new(manifests.into_iter());
+    let next = || queue.lock().unwrap().next();
+    let error = Mutex::new(None);
+
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| {
+                while let Some((path, manifest)) = next() {
+                    if error.lock().unwrap().is_some() {
+                        break;
+                    }
+
+                    let mut out = Vec::new();
+                    let mut log = Vec::new();
+                    isolate_manifest(args, path, manifest, &state, &mut out, &mut log);
+
+                    if let Err(e) = emit(&out, &log, state.log_file.as_ref()) {
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                }
+            });
+        }
+    });
 
-    for path in files {
-        let reify_status = reify_manifest(&args, &path, success)?;
+    match error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => finish(args, state, started),
+    }
+}
 
-        if !reify_status.success {
-            success = false;
+/// Verify the signatures of the project config and manifests and set up the
+/// state shared by all manifests of a run
+fn run_state(
+    args: &Args,
+    files: &[PathBuf],
+    fs_cache: Arc<FsCache>,
+    log_file: Option<File>,
+    cancel: CancellationToken,
+) -> Result<RunState> {
+    let project = project::load()?;
+    if let Some(trusted_keys) = &args.trusted_keys {
+        signature::verify_project(&project, trusted_keys)?;
+        for path in files.iter() {
+            signature::verify(path, trusted_keys)?;
         }
+    }
 
-        // Only write back to manifest file if updated and not dry run
-        if reify_status.updated && !args.dry_run {
-            fs::write(&path, &reify_status.output)?;
+    Ok(RunState {
+        approvals: if args.require_approval {
+            Some(Approvals::load()?)
+        } else {
+            None
+        },
+        log_file,
+        project,
+        fs_cache,
+        git_cache: if args.git_fast_path {
+            let path = project::state_dir()?.join("git-shas.json");
+            GitCache::load(&path)?.map(Arc::new)
+        } else {
+            None
+        },
+        path_base: match args.relative_to {
+            Some(RelativeTo::Cwd) => Some(env::current_dir()?),
+            Some(RelativeTo::Root) => project::state_dir()?.parent().map(PathBuf::from),
+            Some(RelativeTo::Git) => Some(git::repo_root(Path::new("."))?),
+            Some(RelativeTo::Manifest) | None => None,
+        },
+        dirty: if args.require_clean_tree {
+            Some(git::dirty_files(Path::new("."))?)
+        } else {
+            None
+        },
+        cancel,
+        extra_input: if args.hash_stdin {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes)?;
+            bytes
+        } else {
+            Vec::new()
+        },
+        ..Default::default()
+    })
+}
+
+/// Run a project wide hook in the project root, writing its output to the
+/// log, hooks need to be approved like entries with `--require-approval`
+fn run_hook(hook: &'static str, script: &str, state: &RunState, log: &mut dyn Write) -> Result<()> {
+    if let Some(approvals) = &state.approvals {
+        let config = state.project.config_path();
+        if !approvals.is_approved(&config, &state.project.approval_text(script)) {
+            return Err(Error::HookNotApproved { hook });
         }
     }
+    let command = ExecCommand {
+        script: ["set -xe", script].join("\n"),
+        dir: &state.project.root,
+        env: Vec::new(),
+        stdin: Input::Null,
+        stdout_to: None,
+        cancel: state.cancel.clone(),
+        timeout: None,
+    };
+    let local = Local {
+        shell: state.project.shell.clone(),
+    };
+    match local.execute(&command, log)? {
+        0 => Ok(()),
+        code => Err(Error::HookFailed { hook, code }),
+    }
+}
+
+/// Run the `before_all` hook unless it's a dry run, its failure aborts the
+/// run
+fn run_before_all(args: &Args, state: &RunState, log: &mut dyn Write) -> Result<()> {
+    match state.project.before_all.as_ref().filter(|_| !args.dry_run) {
+        Some(before_all) => run_hook("before_all", before_all, state, log),
+        None => Ok(()),
+    }
+}
+
+/// Run the `after_all` hook unless it's a dry run or the run was interrupted,
+/// its failure is reported like a failed entry
+fn run_after_all(args: &Args, state: &RunState, out: &mut dyn Write, log: &mut dyn Write) {
+    let after_all = state.project.after_all.as_ref();
+    if let Some(after_all) = after_all.filter(|_| !args.dry_run && !state.cancel.is_cancelled()) {
+        if let Err(e) = run_hook("after_all", after_all, state, log) {
+            state.failed.store(true, Ordering::SeqCst);
+            report_failure(args, "after_all", &e, out, log);
+        }
+    }
+}
+
+/// Print run-wide reports and return whether the run succeeded
+fn finish(args: &Args, state: RunState, started: Instant) -> Result<bool> {
+    let mut out = Tee {
+        console: io::stdout(),
+        file: state.log_file.as_ref(),
+    };
+    let mut log = Tee {
+        console: io::stderr(),
+        file: state.log_file.as_ref(),
+    };
+    run_after_all(args, &state, &mut out, &mut log);
+
+    if args.format == Format::Json {
+        let mut report = serde_json::Map::new();
+        if args.dry_run {
+            report.insert("stale".into(), state.stale.into_inner().unwrap().into());
+        }
+        if args.print_inputs {
+            report.insert("inputs".into(), state.inputs.into_inner().unwrap().into());
+        }
+        if args.print_outputs {
+            report.insert("outputs".into(), state.outputs.into_inner().unwrap().into());
+        }
+        serde_json::to_writer_pretty(io::stdout(), &report)?;
+        println!();
+    }
+
+    if args.profile {
+        writeln!(log, "profile: {}", state.profile)?;
+    }
+
+    if let Some(git_cache) = &state.git_cache {
+        git_cache.save()?;
+    }
+
+    let outdated = args.error_on_update && state.executed.into_inner();
+    if outdated {
+        error!("generated files were out of date, commit the regenerated files");
+    }
+    let success = !state.failed.into_inner() && !outdated;
+    let history = state.history.into_inner().unwrap();
+
+    let elapsed = started.elapsed();
+    if args.notify.is_some_and(|secs| elapsed.as_secs() >= secs) {
+        let (title, body) = notify::summary(success, history.len(), elapsed);
+        notify::send(&title, &body);
+    }
+    if !history.is_empty() {
+        history::append(history, success)?;
+    }
 
     Ok(success)
 }
 
+fn process_manifest(
+    args: &Args,
+    path: &Path,
+    manifest: Result<Manifest>,
+    state: &RunState,
+    out: &mut dyn Write,
+    log: &mut dyn Write,
+) -> Result<()> {
+    let reify_status = reify_manifest(args, path, manifest?, state, out, log)?;
+
+    // Only write back to manifest file if updated and not dry run
+    // Manifests are left untouched when interrupted
+    if reify_status.updated && !args.dry_run && !state.cancel.is_cancelled() {
+        debug!("writing back {}", path.display());
+        let started = Instant::now();
+        reify_status.manifest.save()?;
+        state.profile.add(Phase::Write, started.elapsed());
+    }
+
+    Ok(())
+}
+
+/// Reify a loaded manifest and report any error, including one loading it, as
+/// a failure of that manifest instead of aborting the whole run
+fn isolate_manifest(
+    args: &Args,
+    path: &Path,
+    manifest: Result<Manifest>,
+    state: &RunState,
+    out: &mut dyn Write,
+    log: &mut dyn Write,
+) {
+    if let Err(e) = process_manifest(args, path, manifest, state, out, log) {
+        state.failed.store(true, Ordering::SeqCst);
+        let (manifest, e) = match e.in_manifest(path) {
+            Error::Manifest { manifest, source } => (manifest, *source),
+            e => (path.into(), e),
+        };
+        let name = reported_path(state, &manifest).display().to_string();
+        report_failure(args, &name, &e, out, log);
+    }
+}
+
+/// Report a failure outside of any entry in the output, or in the log when
+/// the report is hidden
+fn report_failure(args: &Args, name: &str, e: &Error, out: &mut dyn Write, log: &mut dyn Write) {
+    let reporter = reporter(args);
+    let written = match reporter.style {
+        Style::Hidden => writeln!(log, "error: {name} - {e}"),
+        _ => reporter.failure(out, name, &e.to_string()),
+    };
+    if let Err(write_error) = written {
+        error!("{name} - {e} ({write_error})");
+    }
+}
+
+/// Write buffered manifest output without interleaving it with other threads
+fn emit(out: &[u8], log: &[u8], log_file: Option<&File>) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    let mut stderr = io::stderr().lock();
+    if let Some(mut file) = log_file {
+        file.write_all(log)?;
+        file.write_all(out)?;
+    }
+    stderr.write_all(log)?;
+    stdout.write_all(out)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Set up logging to stderr and the log file, if any
+fn init(args: &Args) -> Result<Option<File>> {
+    let log_file = args.log_file.as_deref().map(logfile::open).transpose();
+
+    let file_layer = match &log_file {
+        Ok(Some(file)) => file.try_clone().ok().map(|file| {
+            fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_target(false)
+        }),
+        _ => None,
+    };
+
+    // Digests are logged at debug level whatever the log level
+    let mut filter = Targets::new().with_default(Level::from(args.log_level));
+    if args.debug_hash {
+        filter = filter.with_target(entry::DEBUG_HASH_TARGET, Level::DEBUG);
+    }
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer()
+                .with_writer(io::stderr)
+                .with_target(false)
+                .without_time(),
+        )
+        .with(file_layer)
+        .init();
+
+    log_file
+}
+
 fn main() {
-    let args = Args::parse();
+    match plugin::dispatch(&env::args_os().collect::<Vec<_>>()) {
+        Ok(Some(code)) => std::process::exit(code),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    let args = match config::parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
 
-    let success = match start(&args) {
+    let success = match init(&args).and_then(|log_file| start(&args, log_file)) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("Error: {e}");
+            error!("{e}");
             false
         }
     };
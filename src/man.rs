@@ -0,0 +1,88 @@
+use std::io::Write;
+
+use clap::CommandFactory;
+use clap_mangen::Man;
+
+use crate::error::Result;
+use crate::{entry, project, Args};
+
+const ENVIRONMENT: &[(&str, &str)] = &[
+    ("NO_COLOR", "Disable colored output when --color is auto"),
+    (
+        "XDG_CONFIG_HOME",
+        "Directory of the user config resha/config.yml, defaults to ~/.config",
+    ),
+    (
+        "XDG_DATA_HOME",
+        "Directory of the approved commands, defaults to ~/.local/share",
+    ),
+    (
+        "XDG_CACHE_HOME",
+        "Directory of the downloaded remote files, defaults to ~/.cache",
+    ),
+    (
+        "files, required_files, outputs",
+        "Set for cmd to the newline separated files of its entry",
+    ),
+    ("RESHA_ENTRY_NAME", "Set for cmd to the name of its entry"),
+    (
+        "RESHA_MANIFEST_PATH, RESHA_MANIFEST_DIR",
+        "Set for cmd to the manifest of its entry and its directory",
+    ),
+    (
+        "RESHA_DRY_RUN",
+        "Set for cmd to 1 with --dry-run, otherwise 0",
+    ),
+    (
+        "RESHA",
+        "Set for plugins to the path of the resha executable",
+    ),
+    (
+        "RESHA_STATE_DIR",
+        "Set for plugins to the .resha directory of the project",
+    ),
+    (
+        "RESHA_PROJECT_ROOT",
+        "Set for plugins to the project root when it has a config",
+    ),
+];
+
+const EXIT_STATUS: &[(&str, &str)] = &[
+    (
+        "0",
+        "All entries are up to date or were reified successfully",
+    ),
+    (
+        "1",
+        "An entry failed, is stale in a dry run, or an error occurred",
+    ),
+];
+
+fn render_section(w: &mut dyn Write, title: &str, items: &[(&str, &str)]) -> Result<()> {
+    writeln!(w, ".SH \"{title}\"")?;
+    for (tag, text) in items {
+        writeln!(w, ".TP\n\\fB{tag}\\fR\n{text}")?;
+    }
+    Ok(())
+}
+
+/// Render a roff man page of the command line, manifest and project config
+/// keys, environment and exit status
+pub fn render(w: &mut dyn Write) -> Result<()> {
+    let man = Man::new(Args::command());
+    man.render_title(w)?;
+    man.render_name_section(w)?;
+    man.render_synopsis_section(w)?;
+    man.render_description_section(w)?;
+    man.render_options_section(w)?;
+    man.render_subcommands_section(w)?;
+
+    render_section(w, "MANIFEST", entry::KEYS)?;
+    render_section(w, "PROJECT CONFIG", project::KEYS)?;
+    render_section(w, "ENVIRONMENT", ENVIRONMENT)?;
+    render_section(w, "EXIT STATUS", EXIT_STATUS)?;
+
+    man.render_version_section(w)?;
+    man.render_authors_section(w)?;
+    Ok(())
+}
@@ -1,6 +1,330 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use strict_yaml_rust::parser::{MarkedEventReceiver, Parser};
+use strict_yaml_rust::scanner::Marker;
+use strict_yaml_rust::{Event, ScanError, StrictYaml as Yaml, StrictYamlLoader};
+
+use crate::entry::{Entry, FromYaml};
+use crate::error::{Error, Result};
+use crate::graph;
+
 #[derive(Debug)]
 pub struct ReifyStatus {
-    pub output: String,
-    pub success: bool,
+    pub manifest: Manifest,
     pub updated: bool,
 }
+
+/// Manifest file and its entries, written back keeping the leading comments
+/// and line endings of the original file
+#[derive(Debug)]
+pub struct Manifest {
+    path: PathBuf,
+    preamble: String,
+    crlf: bool,
+    /// Group of every YAML document in the file, `None` for plain lists of
+    /// entries
+    sections: Vec<Option<String>>,
+    entries: Vec<Entry>,
+}
+
+impl Manifest {
+    /// Empty manifest to be saved at `path`
+    pub fn new(path: &Path) -> Self {
+        Manifest {
+            path: path.into(),
+            preamble: String::new(),
+            crlf: false,
+            sections: vec![None],
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        // A bare file name has an empty parent, the current directory
+        let dir = match path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+            Some(dir) => dir,
+            None => return Err(Error::InvalidPath(path.into())),
+        };
+        let text = fs::read_to_string(path).map_err(Error::file(path))?;
+        let docs = StrictYamlLoader::load_from_str(&text)?;
+
+        let mut sections = Vec::new();
+        let mut entries = Vec::new();
+        for doc in docs.iter() {
+            // Empty documents, e.g. after a trailing `---`
+            let Some((group, items)) = document_entries(doc)? else {
+                continue;
+            };
+            let section = sections.len();
+            for y in items {
+                let e = Entry::from_yaml(y)
+                    .map(|e| {
+                        e.with_dir(dir)
+                            .with_manifest_path(path)
+                            .with_section(section, group)
+                    })
+                    .map_err(|e| e.in_entry(entries.len() + 1, y["name"].as_str()))?;
+                entries.push(e);
+            }
+            sections.push(group.map(String::from));
+        }
+        // A manifest without entries, e.g. after removing the last one
+        if sections.is_empty() {
+            sections.push(None);
+        }
+        // Fail on load instead of running entries in an arbitrary order
+        graph::topo_order(&entries)?;
+
+        let preamble = text
+            .split_inclusive('\n')
+            .take_while(|line| {
+                let line = line.trim();
+                line.is_empty() || line.starts_with('#')
+            })
+            .collect::<String>();
+
+        Ok(Manifest {
+            path: path.into(),
+            crlf: is_crlf(&text),
+            preamble: preamble.replace("\r\n", "\n"),
+            sections,
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> &mut Vec<Entry> {
+        &mut self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<Entry> {
+        self.entries
+    }
+
+    /// Append an entry, names have to stay unique within a manifest
+    pub fn add_entry(&mut self, entry: Entry) -> Result<()> {
+        if let Some(name) = entry.name() {
+            if self.find_entry(name).is_some() {
+                return Err(Error::DuplicateEntry(name.clone()));
+            }
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn find_entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.is_named(name))
+    }
+
+    pub fn find_entry_mut(&mut self, name: &str) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|e| e.is_named(name))
+    }
+
+    /// Reorder entries by name, keeping unnamed entries last in their
+    /// original order, returns whether the order changed
+    pub fn sort_entries(&mut self) -> bool {
+        let before = self.entries.iter().map(Entry::name).cloned();
+        let before = before.collect::<Vec<_>>();
+        self.entries
+            .sort_by_cached_key(|e| (e.name().is_none(), e.name().clone()));
+        self.entries.iter().map(Entry::name).ne(before.iter())
+    }
+
+    /// Text of the manifest as it would be saved
+    pub fn dump(&self) -> Result<String> {
+        let mut output = self.preamble.clone();
+        for (section, group) in self.sections.iter().enumerate() {
+            if section > 0 {
+                output.push_str("---\n");
+            }
+            let entries = self
+                .entries
+                .iter()
+                .filter(|e| e.section() == section)
+                .collect::<Vec<_>>();
+            // StrictYAML has no empty list, an empty `entries` or document
+            // is read back as no entries
+            if let Some(group) = group {
+                output.push_str(&format!("group: {group}\nentries:\n"));
+            }
+            for e in entries {
+                e.dump(&mut output, None)?;
+            }
+        }
+        if self.crlf {
+            output = output.replace('\n', "\r\n");
+        }
+        Ok(output)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(&self.path, self.dump()?)?;
+        Ok(())
+    }
+}
+
+/// Group and entries of a YAML document of a manifest, either a list of
+/// entries or a mapping of a `group` to its `entries`, `None` for empty
+/// documents
+pub fn document_entries(doc: &Yaml) -> Result<Option<(Option<&str>, &[Yaml])>> {
+    if is_empty(doc) {
+        return Ok(None);
+    }
+    if let Some(items) = doc.as_vec() {
+        return Ok(Some((None, items.as_slice())));
+    }
+    let hash = doc.as_hash().ok_or(Error::ManifestMalformed)?;
+    let known = |k: &Yaml| matches!(k.as_str(), Some("group" | "entries"));
+    if !hash.keys().all(known) {
+        return Err(Error::ManifestMalformed);
+    }
+    let group = doc["group"].as_str().ok_or(Error::ManifestMalformed)?;
+    let items = match &doc["entries"] {
+        Yaml::Array(items) => items.as_slice(),
+        entries if is_empty(entries) => &[],
+        _ => return Err(Error::ManifestMalformed),
+    };
+    Ok(Some((Some(group), items)))
+}
+
+/// Whether a YAML node has no value, e.g. a document or key without content
+fn is_empty(y: &Yaml) -> bool {
+    match y {
+        Yaml::BadValue => true,
+        Yaml::String(s) => s.is_empty(),
+        _ => false,
+    }
+}
+
+/// Records the events of the YAML parser with their position
+#[derive(Default)]
+struct Events(Vec<(Event, Marker)>);
+
+impl MarkedEventReceiver for Events {
+    fn on_event(&mut self, event: Event, mark: Marker) -> std::result::Result<(), ScanError> {
+        self.0.push((event, mark));
+        Ok(())
+    }
+}
+
+/// Index of the event following the node starting at `start`
+fn skip_node(events: &[(Event, Marker)], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, (event, _)) in events.iter().enumerate().skip(start) {
+        match event {
+            Event::SequenceStart(_) | Event::MappingStart(_) => depth += 1,
+            Event::SequenceEnd | Event::MappingEnd => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return i + 1;
+        }
+    }
+    events.len()
+}
+
+/// Index of the list of entries of the document whose root node starts at
+/// `root`, the value of `entries` in group documents
+fn entries_list(events: &[(Event, Marker)], root: usize) -> Option<usize> {
+    if !matches!(events.get(root)?.0, Event::MappingStart(_)) {
+        return Some(root);
+    }
+    let mut key = root + 1;
+    while events.get(key)?.0 != Event::MappingEnd {
+        let value = skip_node(events, key);
+        if matches!(&events[key].0, Event::Scalar(k, ..) if k == "entries") {
+            return Some(value);
+        }
+        key = skip_node(events, value);
+    }
+    None
+}
+
+/// Byte ranges of the entries in raw manifest text, from the line of the `-`
+/// of an entry up to the next entry or the end of its list, found with the
+/// YAML parser so entries can be edited without touching the formatting of
+/// the others
+pub fn entry_spans(text: &str) -> Result<Vec<Range<usize>>> {
+    let mut events = Events::default();
+    Parser::new(text.chars()).load(&mut events, true)?;
+    let events = events.0;
+
+    // Markers count characters, not bytes
+    let offsets = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([text.len()])
+        .collect::<Vec<_>>();
+    let offset = |mark: &Marker| offsets[mark.index().min(text.len())];
+    let line_start = |at: usize| text[..at].rfind('\n').map_or(0, |i| i + 1);
+    // Only whitespace separates the `-` of an item from its content
+    let item_start = |mark: &Marker| {
+        let at = offset(mark);
+        let dash = text[..at].trim_end().strip_suffix('-').map_or(at, str::len);
+        line_start(dash)
+    };
+
+    let mut spans = Vec::new();
+    let documents = events
+        .iter()
+        .enumerate()
+        .filter(|(_, (event, _))| *event == Event::DocumentStart);
+    for (document, _) in documents {
+        let Some(list) = entries_list(&events, document + 1) else {
+            continue;
+        };
+        if !matches!(events[list].0, Event::SequenceStart(_)) {
+            continue;
+        }
+
+        let mut starts = Vec::new();
+        let mut item = list + 1;
+        while events[item].0 != Event::SequenceEnd {
+            // Mappings are marked after their first key, the key itself
+            // starts the content
+            let content = match events[item].0 {
+                Event::MappingStart(_) | Event::SequenceStart(_) => &events[item + 1].1,
+                _ => &events[item].1,
+            };
+            starts.push(item_start(content));
+            item = skip_node(&events, item);
+        }
+        let end = line_start(offset(&events[item].1));
+        let ends = starts.iter().skip(1).copied().chain([end]);
+        spans.extend(starts.iter().zip(ends).map(|(&start, end)| start..end));
+    }
+    Ok(spans)
+}
+
+/// Convert the line breaks of a rewritten manifest to the style of its
+/// original text, so checkouts with CRLF line endings don't get whole-file
+/// diffs
+pub fn match_line_endings(original: &str, output: &str) -> String {
+    let output = output.replace("\r\n", "\n");
+    if is_crlf(original) {
+        output.replace('\n', "\r\n")
+    } else {
+        output
+    }
+}
+
+fn is_crlf(text: &str) -> bool {
+    text.find('\n').is_some_and(|i| text[..i].ends_with('\r'))
+}
+
+/// Write back a manifest keeping the line endings of the existing file
+pub fn write(path: &Path, output: &str) -> Result<()> {
+    let original = fs::read_to_string(path).unwrap_or_default();
+    fs::write(path, match_line_endings(&original, output))?;
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::process;
+
+use duct::cmd;
+use tracing::{debug, warn};
+
+use crate::IoClass;
+
+/// Lower the CPU and I/O priority of resha, inherited by all commands it runs,
+/// failures only warn since the run works either way
+pub fn lower(nice: Option<i32>, ionice: Option<IoClass>) {
+    let pid = process::id().to_string();
+
+    if let Some(nice) = nice {
+        debug!("setting niceness to {nice}");
+        let res = cmd!("renice", "-n", nice.to_string(), "-p", &pid)
+            .stdout_null()
+            .stderr_null()
+            .run();
+        if res.is_err() {
+            warn!("couldn't set niceness with renice");
+        }
+    }
+
+    if let Some(ionice) = ionice {
+        let class = match ionice {
+            IoClass::Realtime => "1",
+            IoClass::BestEffort => "2",
+            IoClass::Idle => "3",
+        };
+        debug!("setting I/O scheduling class to {class}");
+        let res = cmd!("ionice", "-c", class, "-p", &pid)
+            .stdout_null()
+            .stderr_null()
+            .run();
+        if res.is_err() {
+            warn!("couldn't set I/O priority with ionice");
+        }
+    }
+}
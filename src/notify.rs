@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use duct::cmd;
+use tracing::{debug, warn};
+
+/// Show a desktop notification with notify-send, osascript or a PowerShell
+/// toast, failures only warn since the run is already over
+pub fn send(title: &str, body: &str) {
+    debug!("notifying: {title} - {body}");
+    let expr = if cfg!(target_os = "macos") {
+        let script = format!("display notification {body:?} with title {title:?}");
+        cmd!("osascript", "-e", script)
+    } else if cfg!(windows) {
+        let script = format!(
+            "New-BurntToastNotification -Text '{}', '{}'",
+            title.replace('\'', "''"),
+            body.replace('\'', "''")
+        );
+        cmd!("powershell", "-NoProfile", "-Command", script)
+    } else {
+        cmd!("notify-send", title, body)
+    };
+
+    if expr.stdout_null().stderr_null().run().is_err() {
+        warn!("couldn't show a desktop notification");
+    }
+}
+
+/// Summary of a finished run for its notification
+pub fn summary(success: bool, entries: usize, elapsed: Duration) -> (String, String) {
+    let title = if success {
+        "resha succeeded"
+    } else {
+        "resha failed"
+    };
+    let body = format!("ran {entries} entries in {}s", elapsed.as_secs());
+    (title.into(), body)
+}
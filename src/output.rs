@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// Writer passing through only the first and last lines of the output,
+/// everything in between is counted and left out
+pub struct Truncate<'a> {
+    w: &'a mut dyn Write,
+    head: usize,
+    tail: VecDeque<Vec<u8>>,
+    tail_len: usize,
+    omitted: usize,
+}
+
+impl<'a> Truncate<'a> {
+    /// Keep at most `max_lines` lines, all of them if not set
+    pub fn new(w: &'a mut dyn Write, max_lines: Option<usize>) -> Self {
+        let (head, tail_len) = match max_lines {
+            Some(max) => (max - max / 2, max / 2),
+            None => (usize::MAX, 0),
+        };
+        Self {
+            w,
+            head,
+            tail: VecDeque::new(),
+            tail_len,
+            omitted: 0,
+        }
+    }
+
+    /// Write the last lines after a note about how many were left out
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.omitted > 0 {
+            writeln!(self.w, "... {} lines omitted ...", self.omitted)?;
+        }
+        for line in self.tail.drain(..) {
+            self.w.write_all(&line)?;
+        }
+        self.w.flush()
+    }
+}
+
+impl Write for Truncate<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.split_inclusive(|b| *b == b'\n') {
+            let complete = chunk.ends_with(b"\n");
+            if self.head > 0 {
+                self.w.write_all(chunk)?;
+                if complete {
+                    self.head -= 1;
+                }
+                continue;
+            }
+
+            match self.tail.back_mut() {
+                Some(line) if !line.ends_with(b"\n") => line.extend_from_slice(chunk),
+                _ => self.tail.push_back(chunk.to_vec()),
+            }
+            // A partial line is only dropped once it's complete
+            while self.tail.len() > self.tail_len
+                && self.tail.front().is_some_and(|l| l.ends_with(b"\n"))
+            {
+                self.tail.pop_front();
+                self.omitted += 1;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Writer passing output through while keeping a copy of it
+pub struct Capture<'a> {
+    w: &'a mut dyn Write,
+    pub captured: Vec<u8>,
+}
+
+impl<'a> Capture<'a> {
+    pub fn new(w: &'a mut dyn Write) -> Self {
+        Self {
+            w,
+            captured: Vec::new(),
+        }
+    }
+}
+
+impl Write for Capture<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.w.write(buf)?;
+        self.captured.extend_from_slice(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::CommandFactory;
+
+use crate::error::Result;
+use crate::project;
+use crate::Args;
+
+/// Executable named `resha-<name>` in one of the directories of `PATH`
+fn find(name: &str) -> Option<PathBuf> {
+    let file = format!("resha-{name}{}", env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+}
+
+/// Run `resha-<name>` with the remaining arguments when the first argument
+/// isn't an option, a subcommand or an existing manifest, and return its exit
+/// code
+pub fn dispatch(args: &[OsString]) -> Result<Option<i32>> {
+    let Some(name) = args.get(1).and_then(|a| a.to_str()) else {
+        return Ok(None);
+    };
+    if name.starts_with('-') || Path::new(name).exists() {
+        return Ok(None);
+    }
+    if name == "help" || Args::command().find_subcommand(name).is_some() {
+        return Ok(None);
+    }
+    let Some(path) = find(name) else {
+        return Ok(None);
+    };
+
+    let mut plugin = process::Command::new(path);
+    plugin
+        .args(&args[2..])
+        .env("RESHA", env::current_exe()?)
+        .env("RESHA_STATE_DIR", project::state_dir()?);
+    let project = project::load()?;
+    if project.config_path().is_file() {
+        plugin.env("RESHA_PROJECT_ROOT", project.root);
+    }
+
+    // There is no exit code when killed by a signal
+    Ok(Some(plugin.status()?.code().unwrap_or(1)))
+}
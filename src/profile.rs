@@ -0,0 +1,75 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Phase of a run that time is spent in
+#[derive(Clone, Copy, Debug)]
+pub enum Phase {
+    /// Reading and hashing input files
+    Hash,
+    /// Executing commands
+    Exec,
+    /// Writing manifests back
+    Write,
+}
+
+/// Time spent in each phase, safe to add to from several threads
+#[derive(Debug, Default)]
+pub struct Profile {
+    hash: AtomicU64,
+    exec: AtomicU64,
+    write: AtomicU64,
+}
+
+impl Profile {
+    fn phase(&self, phase: Phase) -> &AtomicU64 {
+        match phase {
+            Phase::Hash => &self.hash,
+            Phase::Exec => &self.exec,
+            Phase::Write => &self.write,
+        }
+    }
+
+    pub fn add(&self, phase: Phase, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.phase(phase).fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.phase(phase).load(Ordering::Relaxed))
+    }
+
+    /// Add all time spent in another profile
+    pub fn merge(&self, other: &Profile) {
+        for phase in [Phase::Hash, Phase::Exec, Phase::Write] {
+            self.add(phase, other.get(phase));
+        }
+    }
+}
+
+/// Time per phase, phases without any time spent are left out
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let phases = [
+            ("hash", Phase::Hash),
+            ("exec", Phase::Exec),
+            ("write", Phase::Write),
+        ];
+        let mut first = true;
+        for (label, phase) in phases {
+            let duration = self.get(phase);
+            if duration.is_zero() {
+                continue;
+            }
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{label} {duration:.1?}")?;
+            first = false;
+        }
+        if first {
+            write!(f, "nothing")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use strict_yaml_rust::{StrictYaml as Yaml, StrictYamlLoader};
+use tracing::debug;
+
+use crate::entry::ExecOptions;
+use crate::error::{Error, Result};
+
+/// Keys of the project config and what they're for, also listed in the man
+/// page
+pub const KEYS: &[(&str, &str)] = &[
+    (
+        "exclude_dirs",
+        "Directories relative to the project root skipped when searching for manifests",
+    ),
+    ("shell", "Shell used to run entry commands instead of bash"),
+    (
+        "before_all",
+        "Script run in the project root once before any entry of a run",
+    ),
+    (
+        "after_all",
+        "Script run in the project root once after all entries of a run",
+    ),
+    (
+        "timeout",
+        "Seconds after which entry commands are killed and fail, 0 for no limit",
+    ),
+    (
+        "max_output_lines",
+        "Only show the first and last lines of longer output",
+    ),
+];
+
+/// Keys that can be overridden for a single run with `--set`
+const SCALAR_KEYS: &[&str] = &[
+    "shell",
+    "before_all",
+    "after_all",
+    "timeout",
+    "max_output_lines",
+];
+
+static OVERRIDES: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Project wide settings shared by every contributor, read from
+/// `.resha/config.yml` in the current directory or one of its parents
+#[derive(Debug, Default)]
+pub struct Project {
+    /// Directories skipped when searching for manifests
+    pub exclude_dirs: Vec<PathBuf>,
+    /// Shell used to run entry commands instead of `bash`
+    pub shell: Option<String>,
+    /// Script run in the project root once before any entry of a run
+    pub before_all: Option<String>,
+    /// Script run in the project root once after all entries of a run
+    pub after_all: Option<String>,
+    /// Entry commands running longer are killed and fail
+    pub timeout: Option<Duration>,
+    /// Only show the first and last lines of longer output, unless the entry
+    /// sets its own limit
+    pub max_output_lines: Option<usize>,
+    /// Directory containing `.resha/`, the current directory without a config
+    pub root: PathBuf,
+}
+
+impl Project {
+    /// Override a setting for this run only, e.g. `--set shell=sh`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let invalid = || Error::InvalidConfigValue {
+            key: key.into(),
+            value: value.into(),
+        };
+        match key {
+            "shell" => self.shell = Some(value.into()),
+            "before_all" => self.before_all = Some(value.into()),
+            "after_all" => self.after_all = Some(value.into()),
+            "timeout" => self.timeout = timeout(value.parse().map_err(|_| invalid())?),
+            "max_output_lines" => {
+                self.max_output_lines = Some(value.parse().map_err(|_| invalid())?)
+            }
+            key => return Err(Error::unknown_config_key(key, SCALAR_KEYS.iter().copied())),
+        }
+        Ok(())
+    }
+
+    /// Path of the project config, what hooks are approved and signed for
+    pub fn config_path(&self) -> PathBuf {
+        self.root.join(".resha/config.yml")
+    }
+
+    /// Text approved for a script, with the shell running it when it isn't
+    /// the default as a different shell changes what the script does
+    pub fn approval_text(&self, script: &str) -> String {
+        match &self.shell {
+            Some(shell) => format!("{shell}\0{script}"),
+            None => script.into(),
+        }
+    }
+
+    /// Hooks that are set, by name
+    pub fn hooks(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("before_all", &self.before_all),
+            ("after_all", &self.after_all),
+        ]
+        .into_iter()
+        .filter_map(|(hook, script)| Some((hook, script.as_deref()?)))
+    }
+
+    /// Options for running entry commands with the settings of the project
+    pub fn exec_options(&self) -> ExecOptions {
+        ExecOptions {
+            shell: self.shell.clone(),
+            max_output_lines: self.max_output_lines,
+            timeout: self.timeout,
+            ..Default::default()
+        }
+    }
+}
+
+/// Timeout of `secs` seconds, `0` for none
+fn timeout(secs: u64) -> Option<Duration> {
+    Some(Duration::from_secs(secs)).filter(|d| !d.is_zero())
+}
+
+fn find() -> Option<PathBuf> {
+    let wd = env::current_dir().ok()?;
+    wd.ancestors()
+        .map(|dir| dir.join(".resha/config.yml"))
+        .find(|path| path.is_file())
+}
+
+fn parse(yaml: &Yaml, root: &Path, path: &Path) -> Result<Project> {
+    let malformed = || Error::ConfigMalformed(path.into());
+    let mut project = Project {
+        root: root.into(),
+        ..Default::default()
+    };
+
+    for (k, v) in yaml.as_hash().ok_or_else(malformed)?.iter() {
+        match k.as_str().ok_or_else(malformed)? {
+            "exclude_dirs" => {
+                for dir in v.as_vec().ok_or_else(malformed)?.iter() {
+                    let dir = root.join(dir.as_str().ok_or_else(malformed)?);
+                    // Directories that don't exist can't contain manifests
+                    if let Ok(dir) = dunce::canonicalize(dir) {
+                        project.exclude_dirs.push(dir);
+                    }
+                }
+            }
+            "shell" => project.shell = Some(v.as_str().ok_or_else(malformed)?.into()),
+            "before_all" => project.before_all = Some(v.as_str().ok_or_else(malformed)?.into()),
+            "after_all" => project.after_all = Some(v.as_str().ok_or_else(malformed)?.into()),
+            "timeout" => {
+                let secs = v.as_str().and_then(|v| v.parse().ok());
+                project.timeout = timeout(secs.ok_or_else(malformed)?);
+            }
+            "max_output_lines" => {
+                let lines = v.as_str().and_then(|v| v.parse().ok());
+                project.max_output_lines = Some(lines.ok_or_else(malformed)?);
+            }
+            key => return Err(Error::unknown_config_key(key, KEYS.iter().map(|(k, _)| *k))),
+        }
+    }
+
+    Ok(project)
+}
+
+/// Directory for project local state, the closest `.resha/` in the current
+/// directory or one of its parents, or a new one in the current directory
+pub fn state_dir() -> Result<PathBuf> {
+    let wd = env::current_dir()?;
+    let dir = wd
+        .ancestors()
+        .map(|dir| dir.join(".resha"))
+        .find(|dir| dir.is_dir());
+    Ok(dir.unwrap_or_else(|| wd.join(".resha")))
+}
+
+/// Override settings of the project config for every later `load`, e.g.
+/// from `--set`, only the first call has an effect
+pub fn set_overrides(overrides: Vec<(String, String)>) {
+    let _ = OVERRIDES.set(overrides);
+}
+
+/// Load the project config, or the defaults if there is none, with the
+/// overrides applied
+pub fn load() -> Result<Project> {
+    let mut project = load_config()?;
+    for (key, value) in OVERRIDES.get().into_iter().flatten() {
+        project.set(key, value)?;
+    }
+    Ok(project)
+}
+
+fn load_config() -> Result<Project> {
+    // Without a config hooks set with `--set` run in the current directory
+    let Some(path) = find() else {
+        return Ok(Project {
+            root: env::current_dir()?,
+            ..Default::default()
+        });
+    };
+    debug!("using project config {}", path.display());
+
+    // The project root is the directory containing `.resha/`
+    let root = path
+        .parent()
+        .and_then(Path::parent)
+        .ok_or_else(|| Error::InvalidPath(path.clone()))?;
+    let yaml_str = fs::read_to_string(&path)?;
+    let docs = StrictYamlLoader::load_from_str(&yaml_str)?;
+    match docs.first() {
+        Some(yaml) => parse(yaml, root, &path),
+        None => Ok(Project {
+            root: root.into(),
+            ..Default::default()
+        }),
+    }
+}
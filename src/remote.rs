@@ -0,0 +1,86 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether a listed file is a remote resource fetched with curl
+pub fn is_url(file: &str) -> bool {
+    file.starts_with("https://") || file.starts_with("http://")
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .map(|d| d.join("resha/urls"))
+        .ok_or(Error::MissingCacheDir)
+}
+
+/// Download a URL into the cache and return the path of the cached copy, a
+/// previous download is revalidated with its ETag and kept when the remote
+/// can't be reached
+pub fn fetch(url: &str) -> Result<PathBuf> {
+    let dir = cache_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    let key = format!("{:x}", Sha256::digest(url));
+    let body = dir.join(&key);
+    let etag = dir.join(format!("{key}.etag"));
+    // Other processes may be fetching the same URL at the same time
+    let count = TEMP_FILES.fetch_add(1, Ordering::SeqCst);
+    let tmp = format!("{key}.{}-{count}", process::id());
+    let tmp_body = dir.join(format!("{tmp}.resha-tmp"));
+    let tmp_etag = dir.join(format!("{tmp}.etag.resha-tmp"));
+
+    let mut args: Vec<OsString> = vec![
+        "-sSL".into(),
+        "-w".into(),
+        "%{http_code}".into(),
+        "-o".into(),
+        tmp_body.clone().into(),
+        "--etag-save".into(),
+        tmp_etag.clone().into(),
+    ];
+    if body.is_file() && etag.is_file() {
+        args.push("--etag-compare".into());
+        args.push(etag.clone().into());
+    }
+    args.push(url.into());
+
+    debug!("fetching {url}");
+    let status = duct::cmd("curl", args).stderr_null().read();
+
+    let fetched = match status.as_deref().map(str::trim) {
+        Ok("200") => {
+            fs::rename(&tmp_body, &body)?;
+            fs::rename(&tmp_etag, &etag)?;
+            true
+        }
+        Ok("304") => {
+            debug!("{url} not modified");
+            true
+        }
+        _ => false,
+    };
+    let _ = fs::remove_file(&tmp_body);
+    let _ = fs::remove_file(&tmp_etag);
+
+    if fetched {
+        Ok(body)
+    } else if body.is_file() {
+        warn!("couldn't fetch {url}, using cached copy");
+        Ok(body)
+    } else {
+        Err(Error::FetchUrl(url.into()))
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+const BOLD: &str = "\x1b[1m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Outcome of an entry as shown in the report
+#[derive(Debug)]
+pub enum Outcome {
+    /// Entry succeeded, with an optional note (empty for none)
+    Ok(&'static str),
+    /// Entry was skipped for a reason
+    Skip(&'static str),
+    /// Entry failed with a message
+    NotOk(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// Test Anything Protocol
+    Tap,
+    /// Human readable ✓/✗ lines per manifest
+    Plain,
+    /// Nothing is reported, e.g. when printing files
+    Hidden,
+}
+
+/// Prints the plan and outcome of each entry of a manifest
+#[derive(Debug)]
+pub struct Reporter {
+    pub style: Style,
+    pub color: bool,
+    pub failures_only: bool,
+}
+
+impl Reporter {
+    fn paint(&self, color: &str, s: &str) -> String {
+        if self.color {
+            format!("{color}{s}{RESET}")
+        } else {
+            s.to_string()
+        }
+    }
+
+    pub fn plan(&self, out: &mut dyn Write, count: usize, manifest: &Path) -> io::Result<()> {
+        match self.style {
+            Style::Tap => writeln!(out, "1..{count}  # manifest {}", manifest.display()),
+            Style::Plain => writeln!(out, "{}", self.paint(BOLD, &manifest.display().to_string())),
+            Style::Hidden => Ok(()),
+        }
+    }
+
+    pub fn entry(
+        &self,
+        out: &mut dyn Write,
+        i: usize,
+        name: &str,
+        outcome: &Outcome,
+    ) -> io::Result<()> {
+        if self.failures_only && !matches!(outcome, Outcome::NotOk(_)) {
+            return Ok(());
+        }
+
+        match (self.style, outcome) {
+            (Style::Hidden, _) => Ok(()),
+            (Style::Tap, Outcome::Ok("")) => writeln!(out, "ok {i} - {name}"),
+            (Style::Tap, Outcome::Ok(note)) => writeln!(out, "ok {i} - {name}  # {note}"),
+            (Style::Tap, Outcome::Skip(reason)) => {
+                writeln!(out, "ok {i} - {name}  # SKIP ({reason})")
+            }
+            (Style::Tap, Outcome::NotOk(msg)) => writeln!(out, "not ok {i} - {name}  # {msg}"),
+            (Style::Plain, Outcome::Ok("")) => writeln!(out, "  {} {name}", self.paint(GREEN, "✓")),
+            (Style::Plain, Outcome::Ok(note)) => {
+                writeln!(out, "  {} {name} ({note})", self.paint(GREEN, "✓"))
+            }
+            (Style::Plain, Outcome::Skip(reason)) => writeln!(
+                out,
+                "  {} {name} (skipped, {reason})",
+                self.paint(YELLOW, "-")
+            ),
+            (Style::Plain, Outcome::NotOk(msg)) => {
+                writeln!(out, "  {} {name}: {msg}", self.paint(RED, "✗"))
+            }
+        }
+    }
+
+    /// Failure outside of any entry, e.g. of a whole manifest or a hook
+    pub fn failure(&self, out: &mut dyn Write, name: &str, msg: &str) -> io::Result<()> {
+        match self.style {
+            Style::Tap => writeln!(out, "not ok - {name}  # {msg}"),
+            Style::Plain => writeln!(out, "{} {name}: {msg}", self.paint(RED, "✗")),
+            Style::Hidden => Ok(()),
+        }
+    }
+
+    /// Header of the following entries of a group
+    pub fn group(&self, out: &mut dyn Write, name: &str) -> io::Result<()> {
+        if self.failures_only {
+            return Ok(());
+        }
+
+        match self.style {
+            Style::Tap => writeln!(out, "# group {name}"),
+            Style::Plain => writeln!(out, "  {}:", self.paint(BOLD, name)),
+            Style::Hidden => Ok(()),
+        }
+    }
+
+    /// Extra information about the last reported entry
+    pub fn diagnostic(&self, out: &mut dyn Write, line: &str) -> io::Result<()> {
+        match self.style {
+            Style::Tap => writeln!(out, "# {line}"),
+            Style::Plain => writeln!(out, "    {line}"),
+            Style::Hidden => Ok(()),
+        }
+    }
+}
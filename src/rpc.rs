@@ -0,0 +1,260 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::approval::Approvals;
+use crate::entry::{Entry, ExecOptions, ReifyFail, ReifySuccess};
+use crate::error::Result;
+use crate::graph;
+use crate::project;
+use crate::signature;
+use crate::{load_manifest, parse_manifest, relative_path, run_hook, Discovery, RunState};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const SERVER_ERROR: i64 = -32000;
+
+struct Server<'a> {
+    discovery: &'a Discovery,
+    exec_options: ExecOptions,
+    /// Approvals and project hooks, shared with regular runs
+    state: RunState,
+    trusted_keys: Option<&'a Path>,
+    subscribed: bool,
+}
+
+fn send(message: &Value) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{message}")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn error(id: &Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Whether an entry was asked for, all entries are when no names are given
+fn selected(params: &Value, e: &Entry) -> bool {
+    match params["entries"].as_array() {
+        Some(names) if !names.is_empty() => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| e.is_named(name)),
+        _ => true,
+    }
+}
+
+impl Server<'_> {
+    fn progress(&self, path: &Path, e: &Entry, status: &str) -> Result<()> {
+        if !self.subscribed {
+            return Ok(());
+        }
+        send(&json!({
+            "jsonrpc": "2.0",
+            "method": "progress",
+            "params": {
+                "manifest": relative_path(path).display().to_string(),
+                "entry": e.name(),
+                "status": status,
+            },
+        }))
+    }
+
+    fn list(&self) -> Result<Value> {
+        let mut entries = Vec::new();
+        for path in self.discovery.find()? {
+            for e in parse_manifest(&path)? {
+                entries.push(json!({
+                    "manifest": relative_path(&path).display().to_string(),
+                    "entry": e.name(),
+                    "sha": e.sha(),
+                }));
+            }
+        }
+        Ok(json!(entries))
+    }
+
+    fn check(&self, params: &Value) -> Result<Value> {
+        let mut results = Vec::new();
+        for path in self.discovery.find()? {
+            for e in parse_manifest(&path)?
+                .iter()
+                .filter(|e| selected(params, e))
+            {
+                let status = match e.dry_run()? {
+                    Ok(_) => "fresh",
+                    Err(ReifyFail::MissingRequiredFiles) => "missing required files",
+                    Err(_) => "stale",
+                };
+                self.progress(&path, e, status)?;
+                results.push(json!({
+                    "manifest": relative_path(&path).display().to_string(),
+                    "entry": e.name(),
+                    "status": status,
+                }));
+            }
+        }
+        Ok(json!(results))
+    }
+
+    fn reify(&self, params: &Value) -> Result<Value> {
+        if let Some(before_all) = &self.state.project.before_all {
+            run_hook("before_all", before_all, &self.state, &mut io::stderr())?;
+        }
+        let results = self.reify_selected(params);
+        if let Some(after_all) = &self.state.project.after_all {
+            run_hook("after_all", after_all, &self.state, &mut io::stderr())?;
+        }
+        results
+    }
+
+    fn reify_selected(&self, params: &Value) -> Result<Value> {
+        let mut results = Vec::new();
+        for path in self.discovery.find()? {
+            let mut manifest = load_manifest(&path)?;
+            if !manifest.entries().iter().any(|e| selected(params, e)) {
+                continue;
+            }
+            if let Some(trusted_keys) = self.trusted_keys {
+                signature::verify(&path, trusted_keys)?;
+            }
+
+            let mut updated = false;
+            for i in graph::topo_order(manifest.entries())? {
+                let e = &mut manifest.entries_mut()[i];
+                if !selected(params, e) {
+                    continue;
+                }
+
+                let approved = match &self.state.approvals {
+                    Some(approvals) => {
+                        let script = self.state.project.approval_text(&e.script());
+                        approvals.is_approved(&path, &script)
+                    }
+                    None => true,
+                };
+
+                self.progress(&path, e, "running")?;
+                let reify_status = if approved {
+                    // Stdout carries the protocol, command output goes to stderr
+                    e.reify(&mut io::stderr(), &self.exec_options)
+                } else {
+                    // Only refuse entries that would actually run
+                    e.dry_run()
+                        .map(|res| res.map_err(|_| ReifyFail::NotApproved))
+                };
+                let (status, reason) = match reify_status? {
+                    Ok(ReifySuccess::ExecSuccess(sha)) => {
+                        e.set_sha(sha);
+                        updated = true;
+                        ("ok", None)
+                    }
+                    Ok(ReifySuccess::Noop) => ("noop", None),
+                    Err(ReifyFail::NotApproved) => ("not_approved", None),
+                    // Entries that ran, or were stopped while running
+                    Err(
+                        fail @ (ReifyFail::ExecFail(_)
+                        | ReifyFail::BeforeFail(_)
+                        | ReifyFail::AfterFail(_)
+                        | ReifyFail::MissingOutputs
+                        | ReifyFail::TimedOut(_)
+                        | ReifyFail::Cancelled),
+                    ) => ("failed", Some(fail.to_string())),
+                    Err(fail) => ("skipped", Some(fail.to_string())),
+                };
+                self.progress(&path, e, status)?;
+                let mut result = json!({
+                    "manifest": relative_path(&path).display().to_string(),
+                    "entry": e.name(),
+                    "status": status,
+                });
+                if let Some(reason) = reason {
+                    result["reason"] = json!(reason);
+                }
+                results.push(result);
+            }
+
+            if updated {
+                manifest.save()?;
+            }
+        }
+        Ok(json!(results))
+    }
+
+    fn handle(&mut self, request: &Value) -> Option<Value> {
+        let id = &request["id"];
+        let params = &request["params"];
+        let result = match request["method"].as_str().unwrap_or_default() {
+            "list" => self.list(),
+            "check" => self.check(params),
+            "reify" => self.reify(params),
+            "subscribe" => {
+                self.subscribed = true;
+                Ok(json!(true))
+            }
+            method => {
+                let message = format!("Unknown method '{method}'");
+                return Some(error(id, METHOD_NOT_FOUND, &message));
+            }
+        };
+
+        // Notifications don't get a response
+        if id.is_null() {
+            return None;
+        }
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error(id, SERVER_ERROR, &e.to_string()),
+        })
+    }
+}
+
+/// Answer JSON-RPC 2.0 requests read line by line from stdin on stdout until
+/// stdin is closed
+pub fn serve(
+    discovery: &Discovery,
+    require_approval: bool,
+    trusted_keys: Option<&Path>,
+) -> Result<bool> {
+    let project = project::load()?;
+    if let Some(trusted_keys) = trusted_keys {
+        signature::verify_project(&project, trusted_keys)?;
+    }
+    let mut server = Server {
+        discovery,
+        exec_options: project.exec_options(),
+        state: RunState {
+            approvals: if require_approval {
+                Some(Approvals::load()?)
+            } else {
+                None
+            },
+            project,
+            ..Default::default()
+        },
+        trusted_keys,
+        subscribed: false,
+    };
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => server.handle(&request),
+            Err(e) => Some(error(&Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        if let Some(response) = response {
+            send(&response)?;
+        }
+    }
+
+    Ok(true)
+}
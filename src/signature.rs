@@ -0,0 +1,117 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use duct::cmd;
+
+use crate::error::{Error, Result};
+use crate::load_manifest;
+use crate::project::Project;
+
+/// Namespace used when signing manifests with `ssh-keygen -Y sign -n resha`
+const SSH_NAMESPACE: &str = "resha";
+
+fn with_extension(path: &Path, ext: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(".");
+    path.push(ext);
+    path.into()
+}
+
+/// Text of a manifest covered by its signature, the manifest as it would be
+/// written back without recorded shas, so recording shas doesn't invalidate
+/// the signature while any change to the entries does
+pub fn signed_content(manifest: &Path) -> Result<String> {
+    let mut manifest = load_manifest(manifest)?;
+    for e in manifest.entries_mut() {
+        e.clear_sha();
+    }
+    manifest.dump()
+}
+
+/// Verify the detached signature next to a manifest file against the trusted
+/// keys, `<manifest>.minisig` is checked with minisign and `<manifest>.sig`
+/// with `ssh-keygen -Y verify`, both cover the `signed_content` of the
+/// manifest
+pub fn verify(manifest: &Path, trusted_keys: &Path) -> Result<()> {
+    verify_content(manifest, signed_content(manifest)?, trusted_keys)
+}
+
+/// Verify the signature of the project config, which sets the hooks and the
+/// shell commands run with, signed as is
+pub fn verify_project(project: &Project, trusted_keys: &Path) -> Result<()> {
+    let config = project.config_path();
+    match fs::read_to_string(&config) {
+        Ok(content) => verify_content(&config, content, trusted_keys),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::file(&config)(e)),
+    }
+}
+
+fn verify_content(manifest: &Path, content: String, trusted_keys: &Path) -> Result<()> {
+    let invalid = || Error::InvalidSignature(manifest.into());
+
+    let minisig = with_extension(manifest, "minisig");
+    if minisig.is_file() {
+        return cmd!(
+            "minisign",
+            "-V",
+            "-q",
+            "-p",
+            trusted_keys,
+            // minisign only verifies files, not its stdin
+            "-m",
+            "/dev/stdin",
+            "-x",
+            &minisig
+        )
+        .stdin_bytes(content)
+        .stdout_null()
+        .stderr_null()
+        .run()
+        .map(|_| ())
+        .map_err(|_| invalid());
+    }
+
+    let sig = with_extension(manifest, "sig");
+    if sig.is_file() {
+        // Look up which of the allowed signers made the signature, required
+        // by `ssh-keygen -Y verify`
+        let principals = cmd!(
+            "ssh-keygen",
+            "-Y",
+            "find-principals",
+            "-f",
+            trusted_keys,
+            "-s",
+            &sig
+        )
+        .stderr_null()
+        .read()
+        .map_err(|_| invalid())?;
+        let principal = principals.lines().next().ok_or_else(invalid)?;
+
+        return cmd!(
+            "ssh-keygen",
+            "-Y",
+            "verify",
+            "-f",
+            trusted_keys,
+            "-I",
+            principal,
+            "-n",
+            SSH_NAMESPACE,
+            "-s",
+            &sig
+        )
+        .stdin_bytes(content)
+        .stdout_null()
+        .stderr_null()
+        .run()
+        .map(|_| ())
+        .map_err(|_| invalid());
+    }
+
+    Err(Error::MissingSignature(manifest.into()))
+}